@@ -0,0 +1,259 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Benchmarks such as COLCAP, a CDT rate, US treasuries, or the DTF rate, imported as pseudo-funds with a unit-value history.
+//!
+//! Each benchmark is a file benchmarks/<name>.csv with a header and one "d/m/y,value" record per line.
+//! If the header is "Fecha,Valor", values are levels of the index. If the header is "Fecha,Tasa", values are annual effective rates, expressed in percentage, and are compounded daily into an index; each rate applies until the next record, and the last rate applies until today.
+//! File benchmarks/benchmarks.txt assigns a benchmark to each fund with one "fund<TAB>benchmark" record per line.
+
+use crate::risk::format_ratio;
+use crate::{parse_date, Currency, FundValue, Series};
+
+/// Initial value of an index compounded from rates, in cents. Large, to keep the precision of daily interest.
+const RATE_INDEX_START: i64 = 100_000_000;
+
+/// Annualized deviations below this are rounding noise, as in benchmarks compounded from a constant rate.
+const MIN_DEVIATION: f64 = 1e-6;
+
+#[derive(Clone, Debug, PartialEq)]
+/// Comparison of a fund against its benchmark, to be stored in benchmark.csv.
+pub struct Comparison {
+    /// First date in which both the fund and the benchmark have a value
+    pub start_date: chrono::NaiveDate,
+    /// Last date in which both the fund and the benchmark have a value
+    pub end_date: chrono::NaiveDate,
+    /// Number of dates in which both the fund and the benchmark have a value
+    pub observations: usize,
+    /// Annual return of the fund minus annual return of the benchmark, expressed in percentage points.
+    pub excess_return: f64,
+    /// Annualized standard deviation of the difference between returns, expressed in percentage.
+    pub tracking_error: f64,
+    /// Sensitivity of the returns of the fund to the returns of the benchmark; NaN if the returns of the benchmark do not vary.
+    pub beta: f64,
+    /// Excess return per unit of tracking error; NaN if the fund follows the benchmark exactly.
+    pub information_ratio: f64,
+}
+
+/// Parses the lines of a benchmark file; see the module documentation. Rates apply until end_date.
+pub fn parse_benchmark<'a, I>(name: &str, file_name: &str, mut lines: I, end_date: chrono::NaiveDate) -> Result<Series, String>
+where
+I: Iterator<Item = &'a str>
+{
+    let is_rate = match lines.next().map(|h| h.trim().to_lowercase()) {
+        Some(header) if header == "fecha,valor" => false,
+        Some(header) if header == "fecha,tasa" => true,
+        _ => return Err(format!("Parsing header at {} line 1: The header must be Fecha,Valor or Fecha,Tasa", file_name)),
+    };
+    let mut records = Vec::new();
+    for (line_index, input) in lines.enumerate() {
+        let line_number = line_index + 2;
+        if input.trim().is_empty() {
+            continue;
+        }
+        let mut fields = input.split(',');
+        let date = parse_date(fields.next().unwrap(), || format!("Parsing date at {} line {} field 1: ", file_name, line_number))?;
+        let value_str = fields.next().ok_or_else(|| format!("Parsing value at {} line {} field 2: No value", file_name, line_number))?.trim();
+        let value = value_str.parse::<f64>().map_err(|e| format!("Parsing value at {} line {} field 2: {}. Is the value {} correctly formatted as a number?", file_name, line_number, e, value_str))?;
+        if !is_rate && value <= 0. {
+            return Err(format!("Parsing value at {} line {} field 2: The value must be positive", file_name, line_number));
+        }
+        records.push((date, value));
+    }
+    records.sort_unstable_by_key(|r| r.0);
+    let fund_value = if is_rate {
+        let mut fund_value = Vec::new();
+        let mut unit_value = RATE_INDEX_START as f64;
+        for (i, (date, rate)) in records.iter().enumerate() {
+            let next_date = records.get(i + 1).map(|r| r.0).unwrap_or_else(|| end_date.max(*date).succ_opt().unwrap());
+            let mut d = *date;
+            while d < next_date {
+                fund_value.push(FundValue { date: d, fund_value: 0, unit_value: unit_value.round() as i64, provenance: None });
+                unit_value *= (1.0 + rate / 100.0).powf(1.0 / 365.0);
+                d = d.succ_opt().unwrap();
+            }
+        }
+        fund_value
+    } else {
+        records
+            .into_iter()
+            .map(|(date, value)| FundValue { date, fund_value: 0, unit_value: (100.0 * value).round() as i64, provenance: None })
+            .collect()
+    };
+    Ok(Series {
+        fund: name.to_string(),
+        balance: Vec::new(),
+        action: Vec::new(),
+        fund_value,
+        currency: Currency::Cop,
+        fee: Vec::new(),
+        closed: None,
+    })
+}
+
+/// Reads every benchmarks/<name>.csv file. Rates apply until end_date. Returns an empty list if the directory does not exist.
+pub fn read_benchmarks(directory: &str, end_date: chrono::NaiveDate) -> Result<Vec<Series>, String> {
+    let path = std::path::Path::new(directory);
+    if !path.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut benchmarks = Vec::new();
+    let entries = std::fs::read_dir(path).map_err(|e| format!("Error reading directory {}: {}", directory, e))?;
+    for entry in entries {
+        let file_path = entry.map_err(|e| format!("Error reading directory {}: {}", directory, e))?.path();
+        if file_path.extension() != Some(std::ffi::OsStr::new("csv")) {
+            continue;
+        }
+        let file_name = file_path.to_string_lossy().to_string();
+        let name = file_path.file_stem().unwrap().to_string_lossy().trim().to_lowercase();
+        let mut lines = Vec::new();
+        for input_res in crate::file_lines(&file_name)? {
+            lines.push(input_res.map_err(|e| format!("Error reading file {}: {}", file_name, e))?);
+        }
+        benchmarks.push(parse_benchmark(&name, &file_name, lines.iter().map(String::as_str), end_date)?);
+    }
+    benchmarks.sort_unstable_by(|a, b| a.fund.cmp(&b.fund));
+    Ok(benchmarks)
+}
+
+/// Reads the assignment of benchmarks to funds as (fund, benchmark) pairs. Returns an empty list if the file does not exist.
+pub fn read_assignments(file_name: &str) -> Result<Vec<(String, String)>, String> {
+    if !std::path::Path::new(file_name).exists() {
+        return Ok(Vec::new());
+    }
+    let mut assignments = Vec::new();
+    for (line_index, input_res) in crate::file_lines(file_name)?.enumerate() {
+        let input = input_res.map_err(|e| format!("Error reading file {}: {}", file_name, e))?;
+        if input.trim().is_empty() {
+            continue;
+        }
+        let mut fields = input.split('\t');
+        let fund = crate::parse_name(fields.next(), || format!("Parsing fund name at {} line {} field 1: ", file_name, line_index + 1))?.to_lowercase();
+        let benchmark = crate::parse_name(fields.next(), || format!("Parsing benchmark name at {} line {} field 2: ", file_name, line_index + 1))?.to_lowercase();
+        assignments.push((fund, benchmark));
+    }
+    Ok(assignments)
+}
+
+/// Compares a fund against its benchmark on the dates in which both have a value. Expects values sorted by date.
+///
+/// Returns None if there are less than 3 common dates, or if the dates span less than a day.
+pub fn compare(fund: &[FundValue], benchmark: &[FundValue]) -> Option<Comparison> {
+    let common: Vec<(chrono::NaiveDate, f64, f64)> = fund
+        .iter()
+        .filter_map(|f| benchmark.iter().find(|b| b.date == f.date).map(|b| (f.date, f.unit_value as f64, b.unit_value as f64)))
+        .filter(|c| c.1 > 0. && c.2 > 0.)
+        .collect();
+    if common.len() < 3 {
+        return None;
+    }
+    let first = common.first().unwrap();
+    let last = common.last().unwrap();
+    let days = (last.0 - first.0).num_days();
+    if days < 1 {
+        return None;
+    }
+    let years = days as f64 / 365.25;
+    let returns: Vec<(f64, f64)> = common.windows(2).map(|w| (w[1].1 / w[0].1 - 1.0, w[1].2 / w[0].2 - 1.0)).collect();
+    let n = returns.len() as f64;
+    let periods_per_year = n / years;
+    let mean_fund = returns.iter().map(|r| r.0).sum::<f64>() / n;
+    let mean_benchmark = returns.iter().map(|r| r.1).sum::<f64>() / n;
+    let covariance = returns.iter().map(|r| (r.0 - mean_fund) * (r.1 - mean_benchmark)).sum::<f64>() / (n - 1.0);
+    let variance_benchmark = returns.iter().map(|r| (r.1 - mean_benchmark).powi(2)).sum::<f64>() / (n - 1.0);
+    let mean_difference = mean_fund - mean_benchmark;
+    let tracking_variance = returns.iter().map(|r| (r.0 - r.1 - mean_difference).powi(2)).sum::<f64>() / (n - 1.0);
+    let tracking_error = tracking_variance.sqrt() * periods_per_year.sqrt();
+    let volatility_benchmark = variance_benchmark.sqrt() * periods_per_year.sqrt();
+    let annual_fund = (last.1 / first.1).powf(1.0 / years) - 1.0;
+    let annual_benchmark = (last.2 / first.2).powf(1.0 / years) - 1.0;
+    let excess_return = annual_fund - annual_benchmark;
+    Some(Comparison {
+        start_date: first.0,
+        end_date: last.0,
+        observations: common.len(),
+        excess_return: 100.0 * excess_return,
+        tracking_error: 100.0 * tracking_error,
+        beta: if volatility_benchmark < MIN_DEVIATION { f64::NAN } else { covariance / variance_benchmark },
+        information_ratio: if tracking_error < MIN_DEVIATION { f64::NAN } else { excess_return / tracking_error },
+    })
+}
+
+/// Formats a record of benchmark.csv, with empty fields if there is no comparison.
+pub fn csv_record(fund: &str, benchmark_name: &str, comparison: Option<&Comparison>) -> String {
+    match comparison {
+        Some(c) => format!(
+            "{},{},{},{},{},{:.4},{:.4},{},{}",
+            fund,
+            benchmark_name,
+            c.start_date,
+            c.end_date,
+            c.observations,
+            c.excess_return,
+            c.tracking_error,
+            format_ratio(c.beta, 4),
+            format_ratio(c.information_ratio, 4)
+        ),
+        None => format!("{},{},,,0,,,,", fund, benchmark_name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn date(day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2021, 1, day).unwrap()
+    }
+    #[test]
+    fn benchmark0() {
+        let s = super::parse_benchmark("colcap", "colcap.csv", vec!["Fecha,Valor", "02/01/2021,1400.5", "01/01/2021,1390", ""].into_iter(), date(31)).unwrap();
+        assert_eq!(s.fund, "colcap");
+        assert_eq!(s.fund_value.iter().map(|v| (v.date, v.unit_value)).collect::<Vec<_>>(), vec![(date(1), 139000), (date(2), 140050)]);
+    }
+    #[test]
+    fn benchmark1() {
+        let s = super::parse_benchmark("dtf", "dtf.csv", vec!["Fecha,Tasa", "01/01/2021,10", "03/01/2021,0"].into_iter(), date(5)).unwrap();
+        assert_eq!(s.fund_value.len(), 5);
+        assert_eq!(s.fund_value[0].unit_value, super::RATE_INDEX_START);
+        assert!(s.fund_value[2].unit_value > s.fund_value[1].unit_value);
+        assert_eq!(s.fund_value[4].unit_value, s.fund_value[2].unit_value);
+    }
+    #[test]
+    fn benchmark2() {
+        assert_eq!(super::parse_benchmark("x", "x.csv", vec!["Date,Value"].into_iter(), date(31)).err(), Some("Parsing header at x.csv line 1: The header must be Fecha,Valor or Fecha,Tasa".to_string()));
+    }
+    #[test]
+    fn compare0() {
+        let values = |unit_values: &[i64]| -> Vec<crate::FundValue> {
+            unit_values.iter().enumerate().map(|(i, v)| crate::FundValue { date: date(i as u32 + 1), fund_value: 0, unit_value: *v, provenance: None }).collect()
+        };
+        let c = super::compare(&values(&[100, 102, 101, 104]), &values(&[1000, 1010, 1005, 1020])).unwrap();
+        assert_eq!(c.observations, 4);
+        assert!(c.beta > 1.5);
+        assert!(c.excess_return > 0.);
+        assert!(c.tracking_error > 0.);
+        let c = super::compare(&values(&[100, 102, 101, 104]), &values(&[100, 102, 101, 104])).unwrap();
+        assert!((c.beta - 1.0).abs() < 1e-9);
+        assert!(c.excess_return.abs() < 1e-9);
+        assert_eq!(super::csv_record("capital", "capital", Some(&c)), "capital,capital,2021-01-01,2021-01-04,4,0.0000,0.0000,1.0000,NA");
+        // A benchmark compounded from a constant rate
+        let dtf = super::parse_benchmark("dtf", "dtf.csv", vec!["Fecha,Tasa", "01/01/2021,10"].into_iter(), date(4)).unwrap();
+        let c = super::compare(&values(&[100, 102, 101, 104]), &dtf.fund_value).unwrap();
+        assert!(c.beta.is_nan() && c.information_ratio.is_finite());
+        assert_eq!(super::csv_record("capital", "dtf", Some(&c)), "capital,dtf,2021-01-01,2021-01-04,4,11742.4681,39.3469,NA,298.4341");
+        assert_eq!(super::csv_record("capital", "dtf", None), "capital,dtf,,,0,,,,");
+    }
+}
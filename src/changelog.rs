@@ -0,0 +1,378 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Append-only log of the changes to funds.dat, in data/audit.txt, for commands changes and undo.
+//!
+//! Each line holds the timestamp, fund, record, date, old values, new values, source, and reason, separated by tabs.
+//! Values are written as the amounts of fondos add, so that a change can be undone with a correction.
+//! Lines with only a timestamp, fund, and description come from versions that did not record values; they cannot be undone.
+//! Changes of the currency and of the annual fees of a fund are logged too, but they cannot be undone.
+//! New imported records are not logged; their provenance tells where they came from.
+
+use crate::edit::{parse_signed_cents, Correction, Operation, Target};
+use crate::{format_cents, Action, ActionKind, Cents, Series, Table};
+use std::io::Write as IoWrite;
+
+pub const CHANGES_USAGE: &str = "fondos changes [--fund <fund>]
+    fondos undo <change number>";
+
+#[derive(Clone, Debug, PartialEq)]
+/// A change to the records of a fund.
+pub struct Change {
+    pub time: chrono::NaiveDateTime,
+    pub fund: String,
+    /// action, balance, or fund-value. Changes to other records, such as currency, fee, and transfer, cannot be undone.
+    pub record: String,
+    pub date: Option<chrono::NaiveDate>,
+    /// Values before the change; empty if the change adds the record.
+    /// Balances have one amount, fund values have the fund value and the unit value, and actions have the amount, the kind, and the investor index.
+    pub old: Vec<String>,
+    /// Values after the change; empty if the change deletes the record.
+    pub new: Vec<String>,
+    /// File and line of an import, or command line.
+    pub source: String,
+    pub reason: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// What an import does when a file gives a different value for a balance or fund value that is already stored.
+pub enum OverwritePolicy {
+    /// Replace the stored value.
+    Overwrite,
+    /// Keep the stored value.
+    Keep,
+    /// Ask in the terminal for each value.
+    Ask,
+    /// Stop without saving anything.
+    Fail,
+}
+
+impl std::str::FromStr for OverwritePolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "overwrite" => Ok(OverwritePolicy::Overwrite),
+            "keep" => Ok(OverwritePolicy::Keep),
+            "ask" => Ok(OverwritePolicy::Ask),
+            "fail" => Ok(OverwritePolicy::Fail),
+            _ => Err(format!("Policy {} not recognized; use overwrite, keep, ask, or fail", s.trim())),
+        }
+    }
+}
+
+impl OverwritePolicy {
+    /// Prints the warning about a different value and decides whether to overwrite the stored value.
+    pub fn overwrite(&self, warning: &str) -> Result<bool, String> {
+        match self {
+            OverwritePolicy::Overwrite => {
+                println!("{}; overwriting it.", warning);
+                Ok(true)
+            }
+            OverwritePolicy::Keep => {
+                println!("{}; keeping the stored value.", warning);
+                Ok(false)
+            }
+            OverwritePolicy::Ask => {
+                print!("{}. Overwrite the stored value? [y/N] ", warning);
+                let io_err = |e| format!("Error reading the answer: {}", e);
+                std::io::stdout().flush().map_err(io_err)?;
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer).map_err(io_err)?;
+                Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+            }
+            OverwritePolicy::Fail => Err(format!("{}. Nothing is saved; run with --on-conflict overwrite or keep to go on", warning)),
+        }
+    }
+}
+
+/// Describes a change of an amount and its size, such as "$1,000.00 to $1,100.00 (+$100.00, +10.00%)".
+pub fn difference(old: Cents, new: Cents) -> String {
+    let change = new - old;
+    let sign = if change > 0 { "+" } else { "" };
+    if old == 0 {
+        format!("{} to {} ({}{})", format_cents(old), format_cents(new), sign, format_cents(change))
+    } else {
+        format!("{} to {} ({}{}, {}{:.2}%)", format_cents(old), format_cents(new), sign, format_cents(change), sign, 100. * change as f64 / old.abs() as f64)
+    }
+}
+
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+impl Change {
+    /// Line of the log, without the line break.
+    pub fn to_line(&self) -> String {
+        let date = self.date.map(|d| d.to_string()).unwrap_or_default();
+        format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", self.time.format(TIME_FORMAT), self.fund, self.record, date, self.old.join(" "), self.new.join(" "), self.source, self.reason)
+    }
+
+    pub fn parse(line: &str) -> Result<Change, String> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let time = chrono::NaiveDateTime::parse_from_str(fields[0], TIME_FORMAT).map_err(|e| format!("Invalid timestamp {}: {}", fields[0], e))?;
+        let values = |field: &str| field.split_whitespace().map(str::to_string).collect();
+        match fields.len() {
+            3 => Ok(Change { time, fund: fields[1].to_string(), record: String::new(), date: None, old: Vec::new(), new: Vec::new(), source: String::new(), reason: fields[2].to_string() }),
+            8 => Ok(Change {
+                time,
+                fund: fields[1].to_string(),
+                record: fields[2].to_string(),
+                date: if fields[3].is_empty() { None } else { Some(fields[3].parse().map_err(|e| format!("Invalid date {}: {}", fields[3], e))?) },
+                old: values(fields[4]),
+                new: values(fields[5]),
+                source: fields[6].to_string(),
+                reason: fields[7].to_string(),
+            }),
+            n => Err(format!("{} fields instead of 8", n)),
+        }
+    }
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.time.format("%Y-%m-%d %H:%M:%S"), self.fund)?;
+        if !self.record.is_empty() {
+            write!(f, " {}", self.record)?;
+        }
+        if let Some(date) = self.date {
+            write!(f, " {}", date)?;
+        }
+        match (self.old.is_empty(), self.new.is_empty()) {
+            (true, true) => write!(f, ": {}", self.reason)?,
+            (true, false) => write!(f, ": added {}; {}", self.new.join(" "), self.reason)?,
+            (false, true) => write!(f, ": deleted {}; {}", self.old.join(" "), self.reason)?,
+            (false, false) => write!(f, ": {} to {}; {}", self.old.join(" "), self.new.join(" "), self.reason)?,
+        }
+        if !self.source.is_empty() {
+            write!(f, " [{}]", self.source)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn action_values(action: &Action) -> Vec<String> {
+    vec![format_cents(action.change), crate::query::kind_name(action.kind).to_string(), action.investor.to_string()]
+}
+
+/// Dated values of the records of a kind, for diff().
+fn records(series: &Series, record: &str) -> Vec<(chrono::NaiveDate, Vec<String>)> {
+    match record {
+        "action" => series.action.iter().map(|a| (a.date, action_values(a))).collect(),
+        "balance" => series.balance.iter().map(|b| (b.date, vec![format_cents(b.balance)])).collect(),
+        "fee" => series.fee.iter().map(|f| (f.date, vec![format!("{}%", f.annual_fee as f64 / 100.0)])).collect(),
+        _ => series.fund_value.iter().map(|v| (v.date, vec![format_cents(v.fund_value), format_cents(v.unit_value)])).collect(),
+    }
+}
+
+/// Changes between two versions of a fund. A record deleted and a record added on the same date make an edit.
+pub fn diff(before: &Series, after: &Series, time: chrono::NaiveDateTime, source: &str, reason: &str) -> Vec<Change> {
+    let mut changes = Vec::new();
+    if before.currency != after.currency {
+        changes.push(Change { time, fund: after.fund.clone(), record: "currency".to_string(), date: None, old: vec![before.currency.to_string()], new: vec![after.currency.to_string()], source: source.to_string(), reason: reason.to_string() });
+    }
+    for record in ["action", "balance", "fund-value", "fee"].iter() {
+        let mut deleted = records(before, record);
+        let mut added = Vec::new();
+        for r in records(after, record) {
+            match deleted.iter().position(|d| *d == r) {
+                Some(i) => {
+                    deleted.remove(i);
+                }
+                None => added.push(r),
+            }
+        }
+        let change = |date, old, new| Change { time, fund: after.fund.clone(), record: record.to_string(), date: Some(date), old, new, source: source.to_string(), reason: reason.to_string() };
+        for (date, old) in deleted {
+            match added.iter().position(|a| a.0 == date) {
+                Some(i) => changes.push(change(date, old, added.remove(i).1)),
+                None => changes.push(change(date, old, Vec::new())),
+            }
+        }
+        changes.extend(added.into_iter().map(|(date, new)| change(date, Vec::new(), new)));
+    }
+    changes.sort_by_key(|c| c.date);
+    changes
+}
+
+/// Applies a correction given in the command line. Returns its description and the changes it made.
+pub fn apply(correction: &Correction, table: &mut Table, today: chrono::NaiveDate, reason: &str) -> Result<(String, Vec<Change>), String> {
+    let before = table.table.iter().find(|s| s.fund == correction.fund).cloned();
+    let description = correction.apply(table, today)?;
+    let changes = match (before, table.table.iter().find(|s| s.fund == correction.fund)) {
+        (Some(before), Some(after)) => diff(&before, after, chrono::Local::now().naive_local(), "command line", reason),
+        _ => Vec::new(),
+    };
+    Ok((description, changes))
+}
+
+/// Builds the correction that reverts change number, if the record still has the values after the change.
+pub fn undo(change: &Change, number: usize, table: &Table) -> Result<Correction, String> {
+    let target = match change.record.as_str() {
+        "action" => Target::Action,
+        "balance" => Target::Balance,
+        "fund-value" => Target::FundValue,
+        _ => return Err(format!("Change {} cannot be undone; only changes to actions, balances, and fund values can", number)),
+    };
+    let date = change.date.ok_or_else(|| format!("Change {} has no date", number))?;
+    let series = table.table.iter().find(|s| s.fund == change.fund).ok_or_else(|| format!("Fund {} not found", change.fund))?;
+    if !change.new.is_empty() && !records(series, &change.record).iter().any(|r| r.0 == date && r.1 == change.new) {
+        return Err(format!("Fund {} has no {} of {} on {} any more; change {} cannot be undone", change.fund, change.record, change.new.join(" "), date, number));
+    }
+    let amount = |values: &[String], i: usize| parse_signed_cents(values.get(i).map(String::as_str), || format!("Parsing amount {} of change {}: ", i + 1, number));
+    let mut correction = Correction { operation: Operation::Edit, target, fund: change.fund.clone(), date, amounts: Vec::new(), investor: None, kind: None };
+    correction.operation = match (change.old.is_empty(), change.new.is_empty()) {
+        (true, true) => return Err(format!("Change {} has no values to restore", number)),
+        (true, false) => Operation::Delete,
+        (false, true) => Operation::Add,
+        (false, false) => Operation::Edit,
+    };
+    correction.amounts = match (correction.operation, target) {
+        (Operation::Delete, Target::Action) | (Operation::Edit, Target::Action) => vec![amount(&change.new, 0)?],
+        (Operation::Delete, _) => Vec::new(),
+        (_, Target::FundValue) => vec![amount(&change.old, 0)?, amount(&change.old, 1)?],
+        _ => vec![amount(&change.old, 0)?],
+    };
+    if target == Target::Action && correction.operation != Operation::Delete {
+        if correction.operation == Operation::Edit {
+            correction.amounts.push(amount(&change.old, 0)?);
+        }
+        correction.kind = Some(if change.old.get(1).map(String::as_str) == Some("fee") { ActionKind::FeeCharge } else { ActionKind::Flow });
+        correction.investor = change.old.get(2).cloned();
+    }
+    Ok(correction)
+}
+
+/// Appends changes to the log.
+pub fn append(log_file_name: &str, changes: &[Change]) -> Result<(), String> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let log_err = |e| format!("Error writing to {}: {}", log_file_name, e);
+    let file = std::fs::OpenOptions::new().create(true).append(true).open(log_file_name).map_err(log_err)?;
+    for change in changes {
+        writeln!(&file, "{}", change.to_line()).map_err(log_err)?;
+    }
+    Ok(())
+}
+
+/// Reads the log. A missing log has no changes.
+pub fn read(log_file_name: &str) -> Result<Vec<Change>, String> {
+    let text = match std::fs::read_to_string(log_file_name) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Error reading the file {}: {}", log_file_name, e)),
+    };
+    text.lines().enumerate().filter(|(_, l)| !l.is_empty()).map(|(i, l)| Change::parse(l).map_err(|e| format!("Parsing {} line {}: {}", log_file_name, i + 1, e))).collect()
+}
+
+/// Numbered list of the changes, optionally of a fund. The number of a change is its position in the log, not counting empty lines.
+pub fn list(changes: &[Change], fund: Option<&str>) -> String {
+    let mut text = String::new();
+    for (i, change) in changes.iter().enumerate().filter(|(_, c)| fund.is_none_or(|f| c.fund == f)) {
+        text.push_str(&format!("{:>5}  {}\n", i + 1, change));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Change;
+    use crate::edit::{Operation, Target};
+    use crate::{Action, ActionKind, Balance, Currency, FundValue, Series, Table};
+    fn date(day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2021, 11, day).unwrap()
+    }
+    fn series() -> Series {
+        Series {
+            fund: "capital".to_string(),
+            balance: vec![Balance { date: date(18), balance: 100000, provenance: None }],
+            action: vec![Action { date: date(19), change: -50000, investor: 1, kind: ActionKind::Flow, source: None, provenance: None }],
+            fund_value: vec![FundValue { date: date(18), fund_value: 900000000, unit_value: 1234567, provenance: None }],
+            currency: Currency::Cop,
+            fee: vec![],
+            closed: None,
+        }
+    }
+    #[test]
+    fn changelog0() {
+        let time = date(25).and_hms_opt(9, 30, 0).unwrap();
+        let mut after = series();
+        after.balance[0].balance = 110000;
+        after.balance.push(Balance { date: date(19), balance: 60000, provenance: None });
+        after.fund_value.clear();
+        let changes = super::diff(&series(), &after, time, "command line", "manual correction");
+        let lines: Vec<_> = changes.iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "2021-11-25 09:30:00 capital balance 2021-11-18: $1,000.00 to $1,100.00; manual correction [command line]",
+                "2021-11-25 09:30:00 capital fund-value 2021-11-18: deleted $9,000,000.00 $12,345.67; manual correction [command line]",
+                "2021-11-25 09:30:00 capital balance 2021-11-19: added $600.00; manual correction [command line]",
+            ]
+        );
+        for c in changes.iter() {
+            assert_eq!(Change::parse(&c.to_line()).as_ref(), Ok(c));
+        }
+        let legacy = Change::parse("2021-11-20T10:00:00\tcapital\tclose on 2021-11-20").unwrap();
+        assert_eq!(legacy.to_string(), "2021-11-20 10:00:00 capital: close on 2021-11-20");
+        assert!(Change::parse("2021-11-20T10:00:00\tcapital").is_err());
+    }
+    #[test]
+    fn changelog3() {
+        let time = date(25).and_hms_opt(9, 30, 0).unwrap();
+        let mut after = series();
+        after.currency = Currency::Usd;
+        after.fee.push(crate::Fee { date: date(1), annual_fee: 175 });
+        let lines: Vec<_> = super::diff(&series(), &after, time, "command line", "command set-fee").iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "2021-11-25 09:30:00 capital currency: COP to USD; command set-fee [command line]",
+                "2021-11-25 09:30:00 capital fee 2021-11-01: added 1.75%; command set-fee [command line]",
+            ]
+        );
+    }
+    #[test]
+    fn changelog1() {
+        let time = date(25).and_hms_opt(9, 30, 0).unwrap();
+        let mut table = Table { table: vec![series()], investor: vec!["Default investor".to_string(), "Investor 1".to_string()], transfer: vec![], reviewed: vec![], import: vec![] };
+        let mut after = series();
+        after.action[0].change = -40000;
+        after.balance[0].balance = 110000;
+        let changes = super::diff(&series(), &after, time, "balances.txt line 3", "re-import");
+        assert_eq!(changes[0].old, vec!["$1,000.00"]);
+        // The table still has the values before the change
+        assert!(super::undo(&changes[0], 1, &table).unwrap_err().starts_with("Fund capital has no balance of $1,100.00 on 2021-11-18 any more"));
+        table.table[0] = after;
+        let correction = super::undo(&changes[0], 1, &table).unwrap();
+        assert_eq!((correction.operation, correction.target, correction.amounts.clone()), (Operation::Edit, Target::Balance, vec![100000]));
+        let correction = super::undo(&changes[1], 2, &table).unwrap();
+        assert_eq!((correction.amounts.clone(), correction.investor.as_deref(), correction.kind), (vec![-40000, -50000], Some("1"), Some(ActionKind::Flow)));
+        let (_, undone) = super::apply(&correction, &mut table, date(25), "undo of change 2").unwrap();
+        assert_eq!((undone[0].old.clone(), undone[0].new.clone()), (changes[1].new.clone(), changes[1].old.clone()));
+        assert_eq!(table.table[0].action[0].change, -50000);
+        let transfer = Change { record: "transfer".to_string(), date: None, ..changes[0].clone() };
+        assert!(super::undo(&transfer, 3, &table).is_err());
+    }
+    #[test]
+    fn changelog2() {
+        assert_eq!(" Keep".parse(), Ok(super::OverwritePolicy::Keep));
+        assert_eq!("always".parse::<super::OverwritePolicy>(), Err("Policy always not recognized; use overwrite, keep, ask, or fail".to_string()));
+        assert_eq!(super::difference(100000, 110000), "$1,000.00 to $1,100.00 (+$100.00, +10.00%)");
+        assert_eq!(super::difference(100000, 1), "$1,000.00 to $0.01 (-$999.99, -100.00%)");
+        assert_eq!(super::difference(0, 5), "$0.00 to $0.05 (+$0.05)");
+        assert!(super::OverwritePolicy::Fail.overwrite("Warning").unwrap_err().starts_with("Warning. Nothing is saved"));
+    }
+}
@@ -0,0 +1,118 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Correlations between the daily returns of the unit values of funds.
+
+use crate::FundValue;
+
+/// Return of the unit value between two consecutive records, dated at the second record.
+pub type Return = (chrono::NaiveDate, f64);
+
+#[derive(Clone, Debug)]
+/// Pairwise correlations between funds over a window of days, to be stored in correlation.csv.
+pub struct CorrelationMatrix {
+    /// Length of the window in days, ending today
+    pub window: i64,
+    /// Correlations in the same order than the list of funds; None if there are not enough common returns.
+    pub matrix: Vec<Vec<Option<f64>>>,
+}
+
+/// Calculates the returns of the unit value. Expects values sorted by date.
+pub fn returns(fund_value: &[FundValue]) -> Vec<Return> {
+    fund_value
+        .windows(2)
+        .filter(|w| w[0].unit_value > 0)
+        .map(|w| (w[1].date, w[1].unit_value as f64 / w[0].unit_value as f64 - 1.0))
+        .collect()
+}
+
+/// Calculates the Pearson correlation of the returns that both funds have on the same dates, from start_date.
+///
+/// Returns None if there are less than 3 common dates, or if any of the funds has constant returns.
+pub fn correlation(returns1: &[Return], returns2: &[Return], start_date: chrono::NaiveDate) -> Option<f64> {
+    let pairs: Vec<(f64, f64)> = returns1
+        .iter()
+        .filter(|r| r.0 >= start_date)
+        .filter_map(|r1| returns2.iter().find(|r2| r2.0 == r1.0).map(|r2| (r1.1, r2.1)))
+        .collect();
+    if pairs.len() < 3 {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let mean1 = pairs.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean2 = pairs.iter().map(|p| p.1).sum::<f64>() / n;
+    let covariance: f64 = pairs.iter().map(|p| (p.0 - mean1) * (p.1 - mean2)).sum();
+    let variance1: f64 = pairs.iter().map(|p| (p.0 - mean1).powi(2)).sum();
+    let variance2: f64 = pairs.iter().map(|p| (p.1 - mean2).powi(2)).sum();
+    if variance1 == 0. || variance2 == 0. {
+        return None;
+    }
+    Some(covariance / (variance1 * variance2).sqrt())
+}
+
+/// Calculates the correlations between every pair of funds over a window of days ending on date.
+pub fn correlation_matrix(returns_vec: &[Vec<Return>], date: chrono::NaiveDate, window: i64) -> CorrelationMatrix {
+    let start_date = date - chrono::Duration::days(window);
+    CorrelationMatrix {
+        window,
+        matrix: returns_vec
+            .iter()
+            .map(|returns1| returns_vec.iter().map(|returns2| correlation(returns1, returns2, start_date)).collect())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::FundValue;
+    fn values(unit_values: &[i64]) -> Vec<FundValue> {
+        let start = chrono::NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        unit_values
+            .iter()
+            .enumerate()
+            .map(|(i, unit_value)| FundValue {
+                date: start + chrono::Duration::days(i as i64),
+                fund_value: 0,
+                unit_value: *unit_value,
+                provenance: None,
+            })
+            .collect()
+    }
+    #[test]
+    fn correlation0() {
+        let r1 = super::returns(&values(&[100, 110, 99, 120, 118]));
+        let r2 = super::returns(&values(&[200, 220, 198, 240, 236]));
+        let r3 = super::returns(&values(&[100, 90, 99, 80, 82]));
+        let start = chrono::NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        assert!((super::correlation(&r1, &r2, start).unwrap() - 1.0).abs() < 1e-9);
+        assert!(super::correlation(&r1, &r3, start).unwrap() < -0.9);
+    }
+    #[test]
+    fn correlation1() {
+        let r1 = super::returns(&values(&[100, 110, 99, 120, 118]));
+        let start = chrono::NaiveDate::from_ymd_opt(2021, 1, 4).unwrap();
+        assert_eq!(super::correlation(&r1, &r1, start), None);
+    }
+    #[test]
+    fn matrix0() {
+        let r = vec![super::returns(&values(&[100, 110, 99, 120])), super::returns(&values(&[100, 100, 100, 100]))];
+        let m = super::correlation_matrix(&r, chrono::NaiveDate::from_ymd_opt(2021, 1, 4).unwrap(), 30);
+        assert!((m.matrix[0][0].unwrap() - 1.0).abs() < 1e-9);
+        assert_eq!(m.matrix[0][1], None);
+        assert_eq!(m.matrix[1][1], None);
+    }
+}
@@ -0,0 +1,160 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Exchange rate between Colombian pesos and US dollars (TRM) to report dollar exposure.
+
+use crate::{parse_date_opt, Cents, Currency};
+
+impl std::fmt::Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Currency::Cop => write!(f, "COP"),
+            Currency::Usd => write!(f, "USD"),
+        }
+    }
+}
+
+impl std::str::FromStr for Currency {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "COP" => Ok(Currency::Cop),
+            "USD" => Ok(Currency::Usd),
+            _ => Err(format!("Currency {} not recognized; use COP or USD", s.trim())),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// Pesos per dollar, sorted by date.
+pub struct ExchangeRate {
+    rate: Vec<(chrono::NaiveDate, f64)>,
+}
+
+/// Parses a number with either '.' or ',' as the decimal separator. If both appear, the last one is the decimal separator.
+fn parse_number(value_str: &str) -> Result<f64, std::num::ParseFloatError> {
+    let normalized = match (value_str.rfind('.'), value_str.rfind(',')) {
+        (Some(dot), Some(comma)) if comma > dot => value_str.replace('.', "").replace(',', "."),
+        (Some(_), Some(_)) => value_str.replace(',', ""),
+        _ => value_str.replace(',', "."),
+    };
+    normalized.parse::<f64>()
+}
+
+impl ExchangeRate {
+    /// Reads the rates from a file with one "d/m/y<TAB>pesos per dollar" record per line, such as trm.txt. Lines starting with "Fecha" are headers.
+    ///
+    /// Returns None if the file does not exist.
+    pub fn read(file_name: &str) -> Result<Option<ExchangeRate>, String> {
+        if !std::path::Path::new(file_name).exists() {
+            return Ok(None);
+        }
+        let mut lines = Vec::new();
+        for input_res in crate::file_lines(file_name)? {
+            lines.push(input_res.map_err(|e| format!("Error reading file {}: {}", file_name, e))?);
+        }
+        ExchangeRate::parse(file_name, lines.iter().map(String::as_str)).map(Some)
+    }
+
+    /// Parses the lines of a rate file; see read().
+    pub fn parse<'a, I>(file_name: &str, lines: I) -> Result<ExchangeRate, String>
+    where
+    I: Iterator<Item = &'a str>
+    {
+        let mut rate = Vec::new();
+        for (line_index, input) in lines.enumerate() {
+            if input.trim().is_empty() || input.starts_with("Fecha") {
+                continue;
+            }
+            let mut fields = input.split('\t');
+            let date = parse_date_opt(fields.next(), || format!("Parsing date at {} line {} field 1: ", file_name, line_index + 1))?;
+            let value_str = fields.next().ok_or_else(|| format!("Parsing rate at {} line {} field 2: No rate", file_name, line_index + 1))?.trim();
+            let value = parse_number(value_str).map_err(|e| format!("Parsing rate at {} line {} field 2: {}. Is the value {} correctly formatted as a number?", file_name, line_index + 1, e, value_str))?;
+            if value <= 0. {
+                return Err(format!("Parsing rate at {} line {} field 2: The rate must be positive", file_name, line_index + 1));
+            }
+            rate.push((date, value));
+        }
+        if rate.is_empty() {
+            return Err(format!("Error reading file {}: No exchange rate records", file_name));
+        }
+        rate.sort_unstable_by_key(|r| r.0);
+        Ok(ExchangeRate { rate })
+    }
+
+    /// Pesos per dollar on a date: the last rate on or before the date, or the first rate for earlier dates.
+    pub fn at(&self, date: chrono::NaiveDate) -> f64 {
+        self.rate.iter().rev().find(|r| r.0 <= date).unwrap_or(&self.rate[0]).1
+    }
+
+    /// Converts an amount in pesos on a date to dollars, in cents of dollar.
+    pub fn to_usd(&self, cents: Cents, date: chrono::NaiveDate) -> Cents {
+        (cents as f64 / self.at(date)).round() as Cents
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// Returns of a fund in pesos and in dollars, to be stored in currency.csv.
+pub struct CurrencyReturn {
+    /// Return in pesos, expressed in percentage.
+    pub return_cop: f64,
+    /// Return in dollars, expressed in percentage.
+    pub return_usd: f64,
+    /// Change of the exchange rate, expressed in percentage. Positive when the peso weakens.
+    pub exchange_rate_change: f64,
+}
+
+/// Splits the return between two unit values in pesos into the return in dollars and the change of the exchange rate.
+pub fn currency_return(rate: &ExchangeRate, first_date: chrono::NaiveDate, first_value: Cents, last_date: chrono::NaiveDate, last_value: Cents) -> CurrencyReturn {
+    let first_rate = rate.at(first_date);
+    let last_rate = rate.at(last_date);
+    CurrencyReturn {
+        return_cop: 100.0 * last_value as f64 / first_value as f64 - 100.0,
+        return_usd: 100.0 * (last_value as f64 / last_rate) / (first_value as f64 / first_rate) - 100.0,
+        exchange_rate_change: 100.0 * last_rate / first_rate - 100.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Currency;
+    fn date(day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2021, 11, day).unwrap()
+    }
+    #[test]
+    fn currency0() {
+        assert_eq!(" usd".parse::<Currency>(), Ok(Currency::Usd));
+        assert_eq!("EUR".parse::<Currency>(), Err("Currency EUR not recognized; use COP or USD".to_string()));
+        assert_eq!(Currency::Cop.to_string(), "COP");
+    }
+    #[test]
+    fn rate0() {
+        let rate = super::ExchangeRate::parse("trm.txt", vec!["Fecha\tTRM", "01/11/2021\t3.800,00", "10/11/2021\t4000.50", "20/11/2021\t4100,5"].into_iter()).unwrap();
+        assert_eq!(rate.at(date(1)), 3800.);
+        assert_eq!(rate.at(date(15)), 4000.5);
+        assert_eq!(rate.at(date(25)), 4100.5);
+        assert_eq!(rate.to_usd(380000, date(5)), 100);
+    }
+    #[test]
+    fn rate1() {
+        let rate = super::ExchangeRate::parse("trm.txt", vec!["01/11/2021\t4000", "20/11/2021\t4400"].into_iter()).unwrap();
+        let r = super::currency_return(&rate, date(1), 1000, date(20), 1210);
+        assert!((r.return_cop - 21.).abs() < 1e-9);
+        assert!((r.exchange_rate_change - 10.).abs() < 1e-9);
+        assert!((r.return_usd - 10.).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,366 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Manual corrections of the records of a fund, for commands add, edit, and delete.
+//!
+//! Every correction is validated, saved through storage::save(), which keeps a backup, and appended to the change log in data/audit.txt.
+
+use crate::transfer::{self, ActionKey};
+use crate::{format_cents, parse_cents, parse_date_opt, parse_name, Action, ActionKind, Balance, Cents, FundValue, Series, Table};
+
+pub const EDIT_USAGE: &str = "fondos add action <fund> <d/m/y> <$amount> [--investor <name or index>] [--kind <flow|fee>]
+    fondos add balance <fund> <d/m/y> <$balance>
+    fondos add fund-value <fund> <d/m/y> <$fund value> <$unit value>
+    fondos edit action <fund> <d/m/y> <$amount> <$new amount> [--investor <name or index>] [--kind <flow|fee>]
+    fondos edit balance <fund> <d/m/y> <$balance>
+    fondos edit fund-value <fund> <d/m/y> <$fund value> <$unit value>
+    fondos delete action <fund> <d/m/y> <$amount>
+    fondos delete balance <fund> <d/m/y>
+    fondos delete fund-value <fund> <d/m/y>";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+    Add,
+    Edit,
+    Delete,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    Action,
+    Balance,
+    FundValue,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A correction of a record of a fund.
+pub struct Correction {
+    pub operation: Operation,
+    pub target: Target,
+    pub fund: String,
+    pub date: chrono::NaiveDate,
+    /// Amounts in the order of EDIT_USAGE.
+    pub amounts: Vec<Cents>,
+    /// Investor name or index, for actions.
+    pub investor: Option<String>,
+    /// Kind, for actions.
+    pub kind: Option<ActionKind>,
+}
+
+/// Parses an amount in pesos that may be negative, such as -$1,000.00.
+pub fn parse_signed_cents<F>(pesos_opt: Option<&str>, error_prefix: F) -> Result<Cents, String>
+where
+F: Fn() -> String
+{
+    match pesos_opt.map(str::trim) {
+        Some(pesos) if pesos.starts_with('-') => parse_cents(Some(&pesos[1..]), error_prefix).map(|c| -c),
+        _ => parse_cents(pesos_opt, error_prefix),
+    }
+}
+
+/// Parses the arguments of commands add, edit, and delete, after the operation.
+pub fn parse_correction<I>(operation: Operation, args_it: &mut I) -> Result<Correction, String>
+where
+I: Iterator<Item = String>
+{
+    let command = match operation {
+        Operation::Add => "add",
+        Operation::Edit => "edit",
+        Operation::Delete => "delete",
+    };
+    let target = match args_it.next().as_deref() {
+        Some("action") => Target::Action,
+        Some("balance") => Target::Balance,
+        Some("fund-value") => Target::FundValue,
+        _ => return Err(format!("Parsing record for {}: Use action, balance, or fund-value. Usage:\n    {}", command, EDIT_USAGE)),
+    };
+    let fund = parse_name(args_it.next().as_deref(), || format!("Parsing fund name for {}: ", command))?.to_lowercase();
+    let date = parse_date_opt(args_it.next().as_deref(), || format!("Parsing date for {}: ", command))?;
+    let n_amounts = match (operation, target) {
+        (Operation::Delete, Target::Action) => 1,
+        (Operation::Delete, _) => 0,
+        (Operation::Edit, Target::Action) | (_, Target::FundValue) => 2,
+        _ => 1,
+    };
+    let mut correction = Correction { operation, target, fund, date, amounts: Vec::new(), investor: None, kind: None };
+    for i in 0..n_amounts {
+        correction.amounts.push(parse_signed_cents(args_it.next().as_deref(), || format!("Parsing amount {} for {}: ", i + 1, command))?);
+    }
+    while let Some(arg) = args_it.next() {
+        match arg.as_str() {
+            "--investor" if target == Target::Action && operation != Operation::Delete => {
+                correction.investor = Some(parse_name(args_it.next().as_deref(), || format!("Parsing option --investor for {}: ", command))?.to_string());
+            }
+            "--kind" if target == Target::Action && operation != Operation::Delete => {
+                correction.kind = match args_it.next().as_deref() {
+                    Some("flow") => Some(ActionKind::Flow),
+                    Some("fee") => Some(ActionKind::FeeCharge),
+                    _ => return Err(format!("Parsing option --kind for {}: Use flow or fee", command)),
+                }
+            }
+            _ => return Err(format!("Unknown option {} for {}. Usage:\n    {}", arg, command, EDIT_USAGE)),
+        }
+    }
+    Ok(correction)
+}
+
+/// Checks the values of an action; fee charges must be negative.
+fn validate_action(action: &Action) -> Result<(), String> {
+    if action.change == 0 {
+        return Err("The amount of an action cannot be zero".to_string());
+    }
+    if action.kind == ActionKind::FeeCharge && action.change > 0 {
+        return Err("The amount of a fee charge must be negative".to_string());
+    }
+    Ok(())
+}
+
+fn validate_fund_value(fund_value: Cents, unit_value: Cents) -> Result<(), String> {
+    if fund_value < 0 || unit_value <= 0 {
+        return Err("The fund value cannot be negative, and the unit value must be positive".to_string());
+    }
+    Ok(())
+}
+
+fn describe_action(action: &Action, investors: &[String]) -> String {
+    let kind = match action.kind {
+        ActionKind::Flow => "flow",
+        ActionKind::FeeCharge => "fee",
+    };
+    format!("action {} {} {} {}", action.date, format_cents(action.change), kind, investors.get(action.investor as usize).map(String::as_str).unwrap_or(""))
+}
+
+impl Correction {
+    /// Applies the correction to the table. Returns a description of the change.
+    pub fn apply(&self, table: &mut Table, today: chrono::NaiveDate) -> Result<String, String> {
+        if self.date > today {
+            return Err(format!("Date {} is in the future", self.date));
+        }
+        let investor = match &self.investor {
+            None => None,
+            Some(name) => Some(
+                table
+                    .investor
+                    .iter()
+                    .position(|i| i.to_lowercase() == name.to_lowercase())
+                    .or_else(|| name.parse::<usize>().ok().filter(|i| *i < table.investor.len()))
+                    .ok_or_else(|| format!("Investor {} not found", name))? as crate::InvestorIndex,
+            ),
+        };
+        let investors = table.investor.clone();
+        let funds: Vec<_> = table.table.iter().map(|s| s.fund.clone()).collect();
+        let series = table.table.iter_mut().find(|s| s.fund == self.fund).ok_or_else(|| format!("Fund {} not found; the funds are: {}", self.fund, funds.join(", ")))?;
+        let date = self.date;
+        // Key of an action that changed or was deleted, whose stored pair no longer holds
+        let mut changed_key = None;
+        let description = match (self.operation, self.target) {
+            (Operation::Add, Target::Action) => {
+                let action = Action { date, change: self.amounts[0], investor: investor.unwrap_or(0), kind: self.kind.unwrap_or(ActionKind::Flow), source: None, provenance: None };
+                validate_action(&action)?;
+                let description = format!("add {}", describe_action(&action, &investors));
+                series.action.push(action);
+                series.action.sort_unstable();
+                description
+            }
+            (Operation::Edit, Target::Action) => {
+                let action = series.action.iter_mut().find(|a| a.date == date && a.change == self.amounts[0]).ok_or_else(|| format!("Fund {} has no action of {} on {}", self.fund, format_cents(self.amounts[0]), date))?;
+                let mut new_action = action.clone();
+                new_action.change = self.amounts[1];
+                new_action.investor = investor.unwrap_or(new_action.investor);
+                new_action.kind = self.kind.unwrap_or(new_action.kind);
+                validate_action(&new_action)?;
+                let description = format!("edit {} to {}", describe_action(action, &investors), describe_action(&new_action, &investors));
+                if new_action.change != action.change || !new_action.is_flow() {
+                    changed_key = Some(ActionKey { fund: self.fund.clone(), date, change: action.change });
+                }
+                *action = new_action;
+                series.action.sort_unstable();
+                description
+            }
+            (Operation::Delete, Target::Action) => {
+                let index = series.action.iter().position(|a| a.date == date && a.change == self.amounts[0]).ok_or_else(|| format!("Fund {} has no action of {} on {}", self.fund, format_cents(self.amounts[0]), date))?;
+                changed_key = Some(ActionKey { fund: self.fund.clone(), date, change: self.amounts[0] });
+                format!("delete {}", describe_action(&series.action.remove(index), &investors))
+            }
+            (Operation::Add, Target::Balance) => {
+                if series.balance.iter().any(|b| b.date == date) {
+                    return Err(format!("Fund {} already has a balance on {}; use edit", self.fund, date));
+                }
+                if self.amounts[0] < 0 {
+                    return Err("A balance cannot be negative".to_string());
+                }
+                series.balance.push(Balance { date, balance: self.amounts[0], provenance: None });
+                series.balance.sort_unstable();
+                format!("add balance {} {}", date, format_cents(self.amounts[0]))
+            }
+            (Operation::Edit, Target::Balance) => {
+                if self.amounts[0] < 0 {
+                    return Err("A balance cannot be negative".to_string());
+                }
+                let balance = series.balance.iter_mut().find(|b| b.date == date).ok_or_else(|| format!("Fund {} has no balance on {}", self.fund, date))?;
+                let description = format!("edit balance {} {} to {}", date, format_cents(balance.balance), format_cents(self.amounts[0]));
+                balance.balance = self.amounts[0];
+                description
+            }
+            (Operation::Delete, Target::Balance) => {
+                let index = series.balance.iter().position(|b| b.date == date).ok_or_else(|| format!("Fund {} has no balance on {}", self.fund, date))?;
+                format!("delete balance {} {}", date, format_cents(series.balance.remove(index).balance))
+            }
+            (Operation::Add, Target::FundValue) => {
+                if series.fund_value.iter().any(|v| v.date == date) {
+                    return Err(format!("Fund {} already has a fund value on {}; use edit", self.fund, date));
+                }
+                validate_fund_value(self.amounts[0], self.amounts[1])?;
+                series.fund_value.push(FundValue { date, fund_value: self.amounts[0], unit_value: self.amounts[1], provenance: None });
+                series.fund_value.sort_unstable();
+                format!("add fund-value {} {} {}", date, format_cents(self.amounts[0]), format_cents(self.amounts[1]))
+            }
+            (Operation::Edit, Target::FundValue) => {
+                validate_fund_value(self.amounts[0], self.amounts[1])?;
+                let value = series.fund_value.iter_mut().find(|v| v.date == date).ok_or_else(|| format!("Fund {} has no fund value on {}", self.fund, date))?;
+                let description = format!("edit fund-value {} {} {} to {} {}", date, format_cents(value.fund_value), format_cents(value.unit_value), format_cents(self.amounts[0]), format_cents(self.amounts[1]));
+                value.fund_value = self.amounts[0];
+                value.unit_value = self.amounts[1];
+                description
+            }
+            (Operation::Delete, Target::FundValue) => {
+                let index = series.fund_value.iter().position(|v| v.date == date).ok_or_else(|| format!("Fund {} has no fund value on {}", self.fund, date))?;
+                let value = series.fund_value.remove(index);
+                format!("delete fund-value {} {} {}", date, format_cents(value.fund_value), format_cents(value.unit_value))
+            }
+        };
+        if let Some(key) = changed_key {
+            transfer::forget(table, &key);
+        }
+        Ok(description)
+    }
+}
+
+/// Finds the open funds that had money in the previous snapshot of balances.txt, but are missing from the snapshot of date.
+///
+/// When all money leaves a fund, the fund disappears from balances.txt and history.txt. Returns the index of each fund and its last balance.
+pub fn missing_funds(table: &Table, snapshot: &[String], date: chrono::NaiveDate) -> Vec<(usize, Balance)> {
+    let previous_date = match table.table.iter().flat_map(|s| s.balance.iter()).map(|b| b.date).filter(|d| *d < date).max() {
+        Some(d) => d,
+        None => return Vec::new(),
+    };
+    table
+        .table
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| s.closed.is_none() && !snapshot.contains(&s.fund) && !s.balance.iter().any(|b| b.date >= date))
+        .filter_map(|(i, s)| s.balance.last().filter(|b| b.date == previous_date && b.balance != 0).map(|b| (i, b.clone())))
+        .collect()
+}
+
+/// Closes a fund on date: records the withdrawal of the last balance and a zero balance. Returns a description of the change.
+pub fn close_fund(series: &mut Series, last_balance: &Balance, date: chrono::NaiveDate) -> String {
+    series.action.push(Action { date, change: -last_balance.balance, investor: 0, kind: ActionKind::Flow, source: None, provenance: None });
+    series.action.sort_unstable();
+    series.balance.retain(|b| b.date != date);
+    series.balance.push(Balance { date, balance: 0, provenance: None });
+    series.balance.sort_unstable();
+    series.closed = Some(date);
+    format!("close on {} with a withdrawal of {} and a zero balance", date, format_cents(-last_balance.balance))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Operation, Target};
+    use crate::{Action, ActionKind, Balance, Currency, Series, Table};
+    fn date(day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2021, 11, day).unwrap()
+    }
+    fn table() -> Table {
+        Table {
+            table: vec![Series {
+                fund: "consumo global".to_string(),
+                balance: vec![Balance { date: date(18), balance: 322896332, provenance: None }],
+                action: vec![Action { date: date(19), change: -321686700, investor: 0, kind: ActionKind::Flow, source: None, provenance: None }],
+                fund_value: vec![],
+                currency: Currency::Cop,
+                fee: vec![],
+                closed: None,
+            }],
+            investor: vec!["Default investor".to_string(), "Investor 1".to_string()],
+            transfer: vec![],
+            reviewed: vec![],
+            import: vec![],
+        }
+    }
+    fn correction(operation: Operation, args: &[&str]) -> Result<super::Correction, String> {
+        super::parse_correction(operation, &mut args.iter().map(|a| a.to_string()))
+    }
+    #[test]
+    fn edit0() {
+        let c = correction(Operation::Add, &["action", "Consumo Global", "19/11/2021", "-$12,096.32", "--investor", "investor 1"]).unwrap();
+        assert_eq!((c.target, c.fund.as_str(), c.amounts.clone()), (Target::Action, "consumo global", vec![-1209632]));
+        let mut table = table();
+        assert_eq!(c.apply(&mut table, date(25)), Ok("add action 2021-11-19 -$12,096.32 flow Investor 1".to_string()));
+        assert_eq!(table.table[0].action[1].investor, 1);
+        let c = correction(Operation::Add, &["balance", "consumo global", "20/11/2021", "$.00"]).unwrap();
+        assert_eq!(c.apply(&mut table, date(25)), Ok("add balance 2021-11-20 $0.00".to_string()));
+        assert_eq!(c.apply(&mut table, date(25)), Err("Fund consumo global already has a balance on 2021-11-20; use edit".to_string()));
+    }
+    #[test]
+    fn edit1() {
+        let mut table = table();
+        let c = correction(Operation::Edit, &["action", "consumo global", "19/11/2021", "-$3,216,867.00", "-$3,216,868.00", "--kind", "fee"]).unwrap();
+        assert_eq!(c.apply(&mut table, date(25)), Ok("edit action 2021-11-19 -$3,216,867.00 flow Default investor to action 2021-11-19 -$3,216,868.00 fee Default investor".to_string()));
+        let c = correction(Operation::Delete, &["action", "consumo global", "19/11/2021", "-$3,216,867.00"]).unwrap();
+        assert_eq!(c.apply(&mut table, date(25)), Err("Fund consumo global has no action of -$3,216,867.00 on 2021-11-19".to_string()));
+        let c = correction(Operation::Delete, &["balance", "consumo global", "18/11/2021"]).unwrap();
+        assert!(c.apply(&mut table, date(25)).is_ok());
+        assert!(table.table[0].balance.is_empty());
+        let c = correction(Operation::Add, &["fund-value", "capital", "18/11/2021", "$1.00", "$1.00"]).unwrap();
+        assert!(c.apply(&mut table, date(25)).unwrap_err().starts_with("Fund capital not found"));
+        assert_eq!(correction(Operation::Add, &["action", "capital", "18/11/2021", "$.00"]).unwrap().apply(&mut table, date(17)), Err("Date 2021-11-18 is in the future".to_string()));
+        assert!(correction(Operation::Delete, &["balance", "capital", "18/11/2021", "--kind", "fee"]).is_err());
+    }
+    #[test]
+    fn edit2() {
+        let mut table = table();
+        let mut capital = table.table[0].clone();
+        capital.fund = "capital".to_string();
+        table.table.push(capital);
+        assert!(super::missing_funds(&table, &["capital".to_string(), "consumo global".to_string()], date(20)).is_empty());
+        assert!(super::missing_funds(&table, &["capital".to_string()], date(18)).is_empty());
+        let missing = super::missing_funds(&table, &["capital".to_string()], date(20));
+        assert_eq!(missing, vec![(0, Balance { date: date(18), balance: 322896332, provenance: None })]);
+        let description = super::close_fund(&mut table.table[0], &missing[0].1, date(20));
+        assert_eq!(description, "close on 2021-11-20 with a withdrawal of -$3,228,963.32 and a zero balance");
+        assert_eq!((table.table[0].balance.last().unwrap().balance, table.table[0].closed), (0, Some(date(20))));
+        assert!(super::missing_funds(&table, &["capital".to_string()], date(21)).is_empty());
+    }
+    #[test]
+    fn edit3() {
+        // Editing a paired action removes its pair, so that the other side is reported again
+        let mut table = table();
+        table.table.push(Series { fund: "capital".to_string(), action: vec![Action { date: date(19), change: 321686700, investor: 0, kind: ActionKind::Flow, source: None, provenance: None }], ..table.table[0].clone() });
+        assert!(crate::transfer::match_transfers(&mut table).is_empty());
+        assert_eq!(table.transfer.len(), 1);
+        let c = correction(Operation::Edit, &["action", "consumo global", "19/11/2021", "-$3,216,867.00", "-$1,000.00"]).unwrap();
+        assert!(c.apply(&mut table, date(25)).is_ok());
+        assert!(table.transfer.is_empty());
+        let unmatched: Vec<_> = crate::transfer::check(&mut table, false).into_iter().map(|u| u.action.to_string()).collect();
+        assert_eq!(unmatched, vec!["capital 19/11/2021 $3,216,867.00", "consumo global 19/11/2021 -$1,000.00"]);
+        // Deleting a reviewed action forgets the review
+        let c = correction(Operation::Delete, &["action", "consumo global", "19/11/2021", "-$1,000.00"]).unwrap();
+        assert!(c.apply(&mut table, date(25)).is_ok());
+        assert_eq!(table.reviewed.iter().map(|r| r.fund.as_str()).collect::<Vec<_>>(), vec!["capital"]);
+    }
+}
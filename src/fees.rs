@@ -0,0 +1,170 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Costs of the funds: management fees estimated from the fee schedule, plus fee charges recorded as actions.
+//!
+//! Management fees are deducted from the unit value, so they never appear in history.txt.
+//! They are estimated day by day as the last balance on or before each day times the annual fee in effect, divided by 365.
+
+use crate::{format_cents, ActionKind, Cents, Series};
+use chrono::Datelike;
+
+#[derive(Clone, Debug, PartialEq)]
+/// Costs of a fund over a period, to be stored in fees.csv.
+pub struct Cost {
+    pub fund: String,
+    pub start_date: chrono::NaiveDate,
+    pub end_date: chrono::NaiveDate,
+    /// Average of the daily balances over the period
+    pub average_balance: Cents,
+    /// Management fees estimated from the balances and the fee schedule
+    pub estimated_fees: Cents,
+    /// Sum of fee charges recorded as actions, as a positive amount
+    pub fee_charges: Cents,
+}
+
+impl Cost {
+    pub fn total(&self) -> Cents {
+        self.estimated_fees + self.fee_charges
+    }
+
+    /// Total cost as a percentage of the average balance, annualized.
+    pub fn annual_percent(&self) -> f64 {
+        let days = (self.end_date - self.start_date).num_days() + 1;
+        if self.average_balance <= 0 {
+            return 0.;
+        }
+        100.0 * self.total() as f64 / self.average_balance as f64 * 365.0 / days as f64
+    }
+}
+
+/// Annual fee in effect on a date, expressed in basis points. Zero before the first fee of the schedule.
+pub fn annual_fee_on(series: &Series, date: chrono::NaiveDate) -> i64 {
+    series.fee.iter().rev().find(|f| f.date <= date).map_or(0, |f| f.annual_fee)
+}
+
+/// Calculates the costs of a fund between two dates, both included. Expects balances sorted by date.
+pub fn cost(series: &Series, start_date: chrono::NaiveDate, end_date: chrono::NaiveDate) -> Cost {
+    let mut balance_sum = 0.;
+    let mut fee_sum = 0.;
+    let mut days = 0;
+    let mut balance_it = series.balance.iter().peekable();
+    let mut balance: Cents = 0;
+    let mut d = start_date;
+    while d <= end_date {
+        while let Some(b) = balance_it.peek() {
+            if b.date > d {
+                break;
+            }
+            balance = b.balance;
+            balance_it.next();
+        }
+        balance_sum += balance as f64;
+        fee_sum += balance as f64 * annual_fee_on(series, d) as f64 / 10000.0 / 365.0;
+        days += 1;
+        d = d.succ_opt().unwrap();
+    }
+    let fee_charges: Cents = -series
+        .action
+        .iter()
+        .filter(|a| a.kind == ActionKind::FeeCharge && a.date >= start_date && a.date <= end_date)
+        .map(|a| a.change)
+        .sum::<Cents>();
+    Cost {
+        fund: series.fund.clone(),
+        start_date,
+        end_date,
+        average_balance: if days > 0 { (balance_sum / days as f64).round() as Cents } else { 0 },
+        estimated_fees: fee_sum.round() as Cents,
+        fee_charges,
+    }
+}
+
+/// Calculates the costs of a fund for every calendar year from its first balance until today. Returns an empty list for funds without fees or fee charges.
+pub fn yearly_costs(series: &Series, today: chrono::NaiveDate) -> Vec<Cost> {
+    let has_charges = series.action.iter().any(|a| a.kind == ActionKind::FeeCharge);
+    let first_date = match series.balance.first() {
+        Some(b) if !series.fee.is_empty() || has_charges => b.date,
+        _ => return Vec::new(),
+    };
+    (first_date.year()..=today.year())
+        .map(|year| {
+            let start_date = chrono::NaiveDate::from_ymd_opt(year, 1, 1).unwrap().max(first_date);
+            let end_date = chrono::NaiveDate::from_ymd_opt(year, 12, 31).unwrap().min(today);
+            cost(series, start_date, end_date)
+        })
+        .collect()
+}
+
+/// Printable summary of the costs of the funds over a period, with the total.
+pub fn summary(costs: &[Cost]) -> String {
+    let mut text = String::new();
+    let header = format!("{:<24} {:>20} {:>20} {:>20} {:>20} {:>10}\n", "Fondo", "Saldo promedio", "Comisión estimada", "Cobros", "Costo total", "% anual");
+    text.push_str(&header);
+    text.push_str(&format!("{}\n", "-".repeat(header.trim_end().chars().count())));
+    for c in costs {
+        text.push_str(&format!("{:<24} {:>20} {:>20} {:>20} {:>20} {:>10.4}\n", c.fund, format_cents(c.average_balance), format_cents(c.estimated_fees), format_cents(c.fee_charges), format_cents(c.total()), c.annual_percent()));
+    }
+    let sum = |f: fn(&Cost) -> Cents| -> Cents { costs.iter().map(f).sum() };
+    text.push_str(&format!("{:<24} {:>20} {:>20} {:>20} {:>20}\n", "Total", format_cents(sum(|c| c.average_balance)), format_cents(sum(|c| c.estimated_fees)), format_cents(sum(|c| c.fee_charges)), format_cents(sum(|c| c.total()))));
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Action, ActionKind, Balance, Currency, Fee, Series};
+    fn date(year: i32, month: u32, day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+    fn series() -> Series {
+        Series {
+            fund: "capital".to_string(),
+            balance: vec![Balance { date: date(2021, 1, 1), balance: 36500000, provenance: None }, Balance { date: date(2021, 7, 1), balance: 73000000, provenance: None }],
+            action: vec![
+                Action { date: date(2021, 7, 1), change: 36500000, investor: 0, kind: ActionKind::Flow, source: None, provenance: None },
+                Action { date: date(2022, 2, 1), change: -1500, investor: 0, kind: ActionKind::FeeCharge, source: None, provenance: None },
+            ],
+            fund_value: vec![],
+            currency: Currency::Cop,
+            fee: vec![Fee { date: date(2021, 1, 1), annual_fee: 100 }, Fee { date: date(2022, 1, 1), annual_fee: 200 }],
+            closed: None,
+        }
+    }
+    #[test]
+    fn fees0() {
+        let s = series();
+        assert_eq!(super::annual_fee_on(&s, date(2020, 12, 31)), 0);
+        assert_eq!(super::annual_fee_on(&s, date(2021, 12, 31)), 100);
+        // 1% of $365,000.00 is $10.00 a day, and 1% of $730,000.00 is $20.00 a day
+        let c = super::cost(&s, date(2021, 1, 1), date(2021, 1, 10));
+        assert_eq!((c.average_balance, c.estimated_fees, c.fee_charges), (36500000, 10000, 0));
+        let c = super::cost(&s, date(2021, 6, 30), date(2021, 7, 1));
+        assert_eq!(c.estimated_fees, 3000);
+    }
+    #[test]
+    fn fees1() {
+        let costs = super::yearly_costs(&series(), date(2022, 3, 1));
+        assert_eq!(costs.len(), 2);
+        assert_eq!(costs[0].estimated_fees, 181 * 1000 + 184 * 2000);
+        assert_eq!((costs[1].start_date, costs[1].end_date), (date(2022, 1, 1), date(2022, 3, 1)));
+        assert_eq!((costs[1].estimated_fees, costs[1].fee_charges), (60 * 4000, 1500));
+        let mut without_fees = series();
+        without_fees.fee.clear();
+        without_fees.action.pop();
+        assert!(super::yearly_costs(&without_fees, date(2022, 3, 1)).is_empty());
+    }
+}
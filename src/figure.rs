@@ -0,0 +1,88 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Output formats of the figures. SVG and PDF are vector formats, suitable for printing and for documents.
+//!
+//! PDF figures are drawn as SVG and then converted, with the text turned into paths using the fonts of the system.
+
+/// Fonts to try, in order, for the text of PDF figures. Carlito has the same metrics as Calibri.
+const PDF_FONT_FAMILIES: &str = "Calibri, Carlito, Liberation Sans, DejaVu Sans, Arial, sans-serif";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Format of the figure files.
+pub enum FigureFormat {
+    Png,
+    Svg,
+    Pdf,
+}
+
+impl FigureFormat {
+    /// Extension of the figure files, without the dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FigureFormat::Png => "png",
+            FigureFormat::Svg => "svg",
+            FigureFormat::Pdf => "pdf",
+        }
+    }
+}
+
+impl std::str::FromStr for FigureFormat {
+    type Err = String;
+    /// Accepts a format such as "svg", an extension such as ".svg", or a file name such as "fondos.svg".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim().to_lowercase();
+        let extension = match std::path::Path::new(&trimmed).extension() {
+            Some(extension) => extension.to_string_lossy().to_string(),
+            None => trimmed.trim_start_matches('.').to_string(),
+        };
+        match extension.as_str() {
+            "png" => Ok(FigureFormat::Png),
+            "svg" => Ok(FigureFormat::Svg),
+            "pdf" => Ok(FigureFormat::Pdf),
+            _ => Err(format!("Figure format {} not recognized; use png, svg, or pdf", s.trim())),
+        }
+    }
+}
+
+/// Converts a figure drawn as SVG to PDF.
+pub fn pdf_from_svg(svg: &str) -> Result<Vec<u8>, String> {
+    use svg2pdf::usvg::{fontdb, PostProcessingSteps, TreeParsing, TreePostProc};
+    let svg = svg.replace("font-family=\"Calibri\"", &format!("font-family=\"{}\"", PDF_FONT_FAMILIES));
+    let mut tree = svg2pdf::usvg::Tree::from_str(&svg, &svg2pdf::usvg::Options::default()).map_err(|e| format!("Error converting figure to PDF: {}", e))?;
+    let mut db = fontdb::Database::new();
+    db.load_system_fonts();
+    tree.postprocess(PostProcessingSteps::default(), &db);
+    Ok(svg2pdf::convert_tree(&tree, svg2pdf::Options::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FigureFormat;
+    #[test]
+    fn figure0() {
+        assert_eq!("SVG".parse::<FigureFormat>(), Ok(FigureFormat::Svg));
+        assert_eq!(".pdf".parse::<FigureFormat>(), Ok(FigureFormat::Pdf));
+        assert_eq!("reunion/fondos.png".parse::<FigureFormat>(), Ok(FigureFormat::Png));
+        assert_eq!("jpg".parse::<FigureFormat>(), Err("Figure format jpg not recognized; use png, svg, or pdf".to_string()));
+    }
+    #[test]
+    fn figure1() {
+        let pdf = super::pdf_from_svg("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"10\" height=\"10\"><rect width=\"5\" height=\"5\" fill=\"#000\"/></svg>").unwrap();
+        assert!(pdf.starts_with(b"%PDF"));
+    }
+}
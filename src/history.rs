@@ -0,0 +1,151 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Import of the actions in history.txt.
+//!
+//! Each action keeps the line it came from, so that importing overlapping exports again adds nothing.
+
+use crate::provenance::Provenance;
+use crate::{format_cents, Action, ActionKind, ActionSource, Cents, Currency, Series, Table};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Debug, PartialEq)]
+/// An action read from a line of the export.
+pub struct HistoryLine {
+    pub fund: String,
+    pub date: chrono::NaiveDate,
+    pub change: Cents,
+    /// Content of the line, without the line break.
+    pub line: String,
+    pub provenance: Option<Provenance>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A stored action within the dates covered by the export, whose line is no longer in it.
+pub struct Dropped {
+    pub fund: String,
+    pub date: chrono::NaiveDate,
+    pub change: Cents,
+    pub source: ActionSource,
+}
+
+impl std::fmt::Display for Dropped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Warning: {} action of {} on {} is no longer in {}: {}", self.fund, format_cents(self.change), self.date, self.source.file, self.source.line)
+    }
+}
+
+/// Imports the lines of an export into the table, in the order of the file. Returns the actions that the bank dropped from the dates covered by the export.
+///
+/// Identical lines are told apart by the number of identical lines before them, so genuine duplicates are kept.
+/// Actions imported before they had a source take the source of the first matching line.
+pub fn import(table: &mut Table, file_name: &str, lines: Vec<HistoryLine>) -> Vec<Dropped> {
+    let mut occurrences = HashMap::<String, u32>::new();
+    let mut imported = HashSet::<ActionSource>::new();
+    // First and last date of each fund in the export
+    let mut covered = HashMap::<String, (chrono::NaiveDate, chrono::NaiveDate)>::new();
+    for HistoryLine { fund, date, change, line, provenance } in lines {
+        let occurrence = occurrences.entry(line.clone()).or_insert(0);
+        let source = ActionSource { file: file_name.to_string(), line, occurrence: *occurrence };
+        *occurrence += 1;
+        imported.insert(source.clone());
+        let range = covered.entry(fund.clone()).or_insert((date, date));
+        *range = (range.0.min(date), range.1.max(date));
+        match table.table.iter_mut().find(|s| s.fund == fund) {
+            Some(series) => {
+                if series.action.iter().any(|a| a.source.as_ref() == Some(&source)) {
+                    continue; // Imported before
+                }
+                match series.action.iter_mut().find(|a| a.source.is_none() && a.is_flow() && a.date == date && a.change == change) {
+                    Some(a) => {
+                        a.source = Some(source);
+                        a.provenance = provenance;
+                    }
+                    None => series.action.push(Action { date, change, investor: 0, kind: ActionKind::Flow, source: Some(source), provenance }),
+                }
+            }
+            None => {
+                table.table.push(Series {
+                    fund,
+                    balance: vec![],
+                    action: vec![Action { date, change, investor: 0, kind: ActionKind::Flow, source: Some(source), provenance }],
+                    fund_value: Vec::<_>::with_capacity(10),
+                    currency: Currency::Cop,
+                    fee: Vec::new(),
+                    closed: None,
+                });
+            }
+        }
+    }
+    let mut dropped = Vec::new();
+    for series in table.table.iter() {
+        if let Some((first_date, last_date)) = covered.get(&series.fund) {
+            for a in series.action.iter().filter(|a| a.date >= *first_date && a.date <= *last_date) {
+                if let Some(source) = a.source.as_ref().filter(|s| s.file == file_name && !imported.contains(s)) {
+                    dropped.push(Dropped { fund: series.fund.clone(), date: a.date, change: a.change, source: source.clone() });
+                }
+            }
+        }
+    }
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HistoryLine;
+    use crate::{Action, ActionKind, Table};
+    fn date(day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2021, 11, day).unwrap()
+    }
+    fn line(day: u32, change: i64) -> HistoryLine {
+        HistoryLine { fund: "capital".to_string(), date: date(day), change, line: format!("{:02}/11/2021\tCapital\tAporte\tAporte\t${}.00", day, change / 100), provenance: None }
+    }
+    fn table() -> Table {
+        Table { table: vec![], investor: vec!["Default investor".to_string()], transfer: vec![], reviewed: vec![], import: vec![] }
+    }
+    #[test]
+    fn history0() {
+        // Importing an overlapping export again adds nothing
+        let mut table = table();
+        assert!(super::import(&mut table, "history.txt", vec![line(1, 100), line(2, 200)]).is_empty());
+        assert!(super::import(&mut table, "history.txt", vec![line(2, 200), line(3, 300)]).is_empty());
+        assert_eq!(table.table[0].action.iter().map(|a| a.change).collect::<Vec<_>>(), vec![100, 200, 300]);
+        // Genuine duplicate lines stay, and are not added again
+        let mut table = self::table();
+        super::import(&mut table, "history.txt", vec![line(1, 100), line(1, 100)]);
+        super::import(&mut table, "history.txt", vec![line(1, 100), line(1, 100)]);
+        assert_eq!(table.table[0].action.len(), 2);
+        assert_eq!(table.table[0].action[1].source.as_ref().map(|s| s.occurrence), Some(1));
+    }
+    #[test]
+    fn history1() {
+        // Actions imported before they had a source take the source of the first matching line
+        let mut table = table();
+        super::import(&mut table, "history.txt", vec![line(1, 100)]);
+        table.table[0].action[0].source = None;
+        table.table[0].action.push(Action { date: date(1), change: 100, investor: 0, kind: ActionKind::Flow, source: None, provenance: None });
+        super::import(&mut table, "history.txt", vec![line(1, 100)]);
+        let action = &table.table[0].action;
+        assert_eq!((action.len(), action[0].source.is_some(), action[1].source.is_some()), (2, true, false));
+        // Dropped lines within the covered dates are reported; lines outside them are not
+        let mut table = self::table();
+        super::import(&mut table, "history.txt", vec![line(1, 100), line(2, 200), line(5, 500)]);
+        let dropped = super::import(&mut table, "history.txt", vec![line(1, 100), line(5, 500)]);
+        assert_eq!(dropped.iter().map(|d| d.to_string()).collect::<Vec<_>>(), vec!["Warning: capital action of $2.00 on 2021-11-02 is no longer in history.txt: 02/11/2021\tCapital\tAporte\tAporte\t$2.00"]);
+        assert!(super::import(&mut table, "history.txt", vec![line(5, 500)]).is_empty());
+    }
+}
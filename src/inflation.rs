@@ -0,0 +1,128 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Consumer price index (IPC, published by DANE) to express amounts and returns in real terms.
+
+use crate::{parse_date_opt, Cents};
+
+#[derive(Clone, Debug)]
+/// Consumer price index, sorted by date.
+pub struct Cpi {
+    index: Vec<(chrono::NaiveDate, f64)>,
+}
+
+impl Cpi {
+    /// Reads the index from a file with one "d/m/y<TAB>index" record per line, such as ipc.txt. Lines starting with "Fecha" are headers.
+    ///
+    /// Returns None if the file does not exist.
+    pub fn read(file_name: &str) -> Result<Option<Cpi>, String> {
+        if !std::path::Path::new(file_name).exists() {
+            return Ok(None);
+        }
+        let mut lines = Vec::new();
+        for input_res in crate::file_lines(file_name)? {
+            lines.push(input_res.map_err(|e| format!("Error reading file {}: {}", file_name, e))?);
+        }
+        Cpi::parse(file_name, lines.iter().map(String::as_str)).map(Some)
+    }
+
+    /// Parses the lines of an index file; see read().
+    pub fn parse<'a, I>(file_name: &str, lines: I) -> Result<Cpi, String>
+    where
+    I: Iterator<Item = &'a str>
+    {
+        let mut index = Vec::new();
+        for (line_index, input) in lines.enumerate() {
+            if input.trim().is_empty() || input.starts_with("Fecha") {
+                continue;
+            }
+            let mut fields = input.split('\t');
+            let date = parse_date_opt(fields.next(), || format!("Parsing date at {} line {} field 1: ", file_name, line_index + 1))?;
+            let value_str = fields.next().ok_or_else(|| format!("Parsing index at {} line {} field 2: No index", file_name, line_index + 1))?.trim();
+            let value = value_str.replace(',', ".").parse::<f64>().map_err(|e| format!("Parsing index at {} line {} field 2: {}. Is the value {} correctly formatted as a number?", file_name, line_index + 1, e, value_str))?;
+            if value <= 0. {
+                return Err(format!("Parsing index at {} line {} field 2: The index must be positive", file_name, line_index + 1));
+            }
+            index.push((date, value));
+        }
+        if index.is_empty() {
+            return Err(format!("Error reading file {}: No index records", file_name));
+        }
+        index.sort_unstable_by_key(|r| r.0);
+        Ok(Cpi { index })
+    }
+
+    /// Index on a date, interpolated linearly between records. Dates outside the records take the nearest record.
+    pub fn at(&self, date: chrono::NaiveDate) -> f64 {
+        match self.index.iter().position(|r| r.0 > date) {
+            Some(0) => self.index[0].1,
+            Some(i) => {
+                let (d0, v0) = self.index[i - 1];
+                let (d1, v1) = self.index[i];
+                v0 + (v1 - v0) * (date - d0).num_days() as f64 / (d1 - d0).num_days() as f64
+            }
+            None => self.index.last().unwrap().1,
+        }
+    }
+
+    /// Inflation between two dates, expressed in percentage.
+    pub fn inflation(&self, from: chrono::NaiveDate, to: chrono::NaiveDate) -> f64 {
+        100.0 * self.at(to) / self.at(from) - 100.0
+    }
+
+    /// Expresses an amount on date in pesos of base_date.
+    pub fn deflate(&self, cents: Cents, date: chrono::NaiveDate, base_date: chrono::NaiveDate) -> Cents {
+        (cents as f64 * self.at(base_date) / self.at(date)).round() as Cents
+    }
+}
+
+/// Real return, given nominal return and inflation, all expressed in percentage.
+pub fn real_return(nominal_return: f64, inflation: f64) -> f64 {
+    100.0 * (1.0 + nominal_return / 100.0) / (1.0 + inflation / 100.0) - 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    fn date(month: u32, day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2021, month, day).unwrap()
+    }
+    fn cpi() -> super::Cpi {
+        super::Cpi::parse("ipc.txt", vec!["Fecha\tIndice", "01/01/2021\t100", "31/01/2021\t103,0", ""].into_iter()).unwrap()
+    }
+    #[test]
+    fn cpi0() {
+        let cpi = cpi();
+        assert_eq!(cpi.at(date(1, 1)), 100.);
+        assert!((cpi.at(date(1, 11)) - 101.).abs() < 1e-9);
+        assert_eq!(cpi.at(date(3, 1)), 103.);
+        assert_eq!(cpi.at(chrono::NaiveDate::from_ymd_opt(2020, 12, 1).unwrap()), 100.);
+    }
+    #[test]
+    fn cpi1() {
+        let cpi = cpi();
+        assert!((cpi.inflation(date(1, 1), date(1, 31)) - 3.).abs() < 1e-9);
+        assert_eq!(cpi.deflate(10300, date(1, 31), date(1, 1)), 10000);
+    }
+    #[test]
+    fn cpi2() {
+        assert_eq!(super::Cpi::parse("ipc.txt", vec!["01/01/2021\tx"].into_iter()).err(), Some("Parsing index at ipc.txt line 1 field 2: invalid float literal. Is the value x correctly formatted as a number?".to_string()));
+    }
+    #[test]
+    fn real0() {
+        assert!((super::real_return(8., 13.) + 4.424778761).abs() < 1e-6);
+    }
+}
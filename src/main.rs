@@ -15,6 +15,27 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+mod benchmark;
+mod changelog;
+mod correlation;
+mod currency;
+mod edit;
+mod fees;
+mod figure;
+mod history;
+mod inflation;
+mod report;
+mod quality;
+mod provenance;
+mod query;
+mod reconcile;
+mod risk;
+mod serve;
+mod storage;
+mod tax;
+mod transfer;
+mod tui;
+
 use serde::{Deserialize, Serialize};
 use std::io::Write as IoWrite;
 
@@ -53,6 +74,7 @@ struct Balance {
     /// Balance in the fund
     /// 
     balance: Cents,
+    provenance: Option<provenance::Provenance>,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
@@ -63,6 +85,7 @@ struct FundValue {
     fund_value: Cents,
     /// Value of a fund unit
     unit_value: Cents,
+    provenance: Option<provenance::Provenance>,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
@@ -73,6 +96,45 @@ struct Action {
     change: Cents,
     /// Index of investor. Valid for deposits or withdrawals.
     investor: InvestorIndex,
+    kind: ActionKind,
+    /// Line of the export that the action was imported from; None for actions recorded by hand.
+    source: Option<ActionSource>,
+    provenance: Option<provenance::Provenance>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
+/// Identity of an imported action, so that importing overlapping exports again adds nothing.
+struct ActionSource {
+    /// Name of the export, such as history.txt.
+    file: String,
+    /// Content of the line.
+    line: String,
+    /// Number of identical lines before this one in the export. Tells genuine duplicates apart.
+    occurrence: u32,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
+/// Kind of an action in a fund.
+enum ActionKind {
+    /// Deposit or withdrawal, including transfers between funds.
+    Flow,
+    /// Fee charged by the fund manager outside the unit value, such as a withdrawal fee. It is a cost, not a withdrawal.
+    FeeCharge,
+}
+
+impl Action {
+    /// Whether the action moves money of the investor into or out of the fund.
+    fn is_flow(&self) -> bool {
+        self.kind == ActionKind::Flow
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
+/// Represents a management fee of a fund, in effect from a date until the next fee.
+struct Fee {
+    date: chrono::NaiveDate,
+    /// Annual fee on the balance, expressed in hundredths of a percentage point (basis points).
+    annual_fee: i64,
 }
 
 type Date = chrono::Date<chrono::Utc>;
@@ -84,15 +146,6 @@ type Variation = (
     f64,
 );
 
-#[derive(Debug)]
-/// Represents a repetition of a fund action.
-struct Repetition {
-    fund_index: usize,
-    action_index: usize,
-    // Let repetition as i32 for subtraction
-    repetition: i32,
-}
-
 #[derive(Clone, Debug)]
 /// Represents a label in the figure.
 struct Label<'label_lifetime> {
@@ -106,12 +159,25 @@ struct Label<'label_lifetime> {
     backend_coord: plotters_backend::BackendCoord,
 }
 
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+/// Currency of the exposure of a fund. Amounts are always recorded in Colombian pesos.
+enum Currency {
+    Cop,
+    Usd,
+}
+
 #[derive(Clone, Debug, Deserialize, Hash, Serialize)]
 struct Series {
     fund: String,
     balance: Vec<Balance>,
     action: Vec<Action>,
     fund_value: Vec<FundValue>,
+    /// Currency of the exposure of the fund; for example, global funds are exposed to US dollars.
+    currency: Currency,
+    /// Schedule of management fees, sorted by date.
+    fee: Vec<Fee>,
+    /// Date when all the money left the fund. Closed funds are left out of the charts and the report.
+    closed: Option<chrono::NaiveDate>,
 }
 
 #[derive(Clone, Debug)]
@@ -126,6 +192,19 @@ struct Table {
     table: Vec<Series>,
     // List of investors. Do not remove elements.
     investor: Vec<String>,
+    /// Pairs of withdrawals and deposits between funds.
+    transfer: Vec<transfer::Transfer>,
+    /// Withdrawals and deposits without a pair that were already reported.
+    reviewed: Vec<transfer::ActionKey>,
+    /// Bank exports that the records came from.
+    import: Vec<provenance::Import>,
+}
+
+impl Table {
+    /// Funds that are not closed; charts and reports leave closed funds out.
+    fn open_series(&self) -> impl Iterator<Item = &Series> {
+        self.table.iter().filter(|s| s.closed.is_none())
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -133,27 +212,251 @@ struct Table {
 struct FundAggregate {
     fund: String,
     /// Return on equity for next-to-last year, expressed in percentage.
-    roe_next_to_last_year: f64,
+    roe_next_to_last_year: Percent,
     /// Return on equity for last year, expressed in percentage.
-    roe_last_year: f64,
+    roe_last_year: Percent,
     /// Return on equity for year to date, expressed in percentage.
-    roe_year_to_date: f64,
+    roe_year_to_date: Percent,
     /// Return on equity for today, expressed in percentage.
-    roe_day: f64,
+    roe_day: Percent,
     /// Return on equity for today, annualized, expressed in percentage.
-    roe_day_annualized: f64,
+    roe_day_annualized: Percent,
     /// Return on equity for the last month, expressed in percentage.
-    roe_month: f64,
+    roe_month: Percent,
     /// Return on equity for the last trimester, expressed in percentage.
-    roe_trimester: f64,
+    roe_trimester: Percent,
     /// Return on equity for the last semester, expressed in percentage.
-    roe_semester: f64,
+    roe_semester: Percent,
     /// Return on equity for the last year, expressed in percentage.
-    roe_year: f64,
+    roe_year: Percent,
     /// Return on equity for the last 2 years, expressed in percentage.
-    roe_2_years: f64,
+    roe_2_years: Percent,
     /// Return on equity from the beginning of the fund, expressed in percentage.
-    roe_total: f64,
+    roe_total: Percent,
+}
+
+/// Command given in the command line.
+#[derive(Debug, PartialEq)]
+enum Command {
+    /// Import balances.txt, history.txt, and profit.txt, then produce figures and data files.
+    Update,
+    /// Set the currency of the exposure of a fund.
+    SetCurrency(String, Currency),
+    /// Produce the figures for the income declaration of a year.
+    TaxReport(i32),
+    /// Set the annual management fee of a fund from a date, in basis points.
+    SetFee(String, chrono::NaiveDate, i64),
+    /// Record a fee charged to a fund outside the unit value.
+    ChargeFee(String, chrono::NaiveDate, Cents),
+    /// Show the costs of the funds between two dates.
+    Fees(chrono::NaiveDate, chrono::NaiveDate),
+    /// Serve the database to a web browser on a port of localhost.
+    Serve(u16),
+    /// Explore the records of each fund in the terminal.
+    Tui,
+    /// List the stored records that match the filters.
+    Show(query::Query),
+    /// Add, edit, or delete an action, balance, or fund value of a fund.
+    Correct(edit::Correction),
+    /// Confirm that a withdrawal and a deposit are the same transfer between funds.
+    Pair(transfer::ActionKey, transfer::ActionKey),
+    /// Check that every withdrawal has a deposit; if true, check the full history again.
+    Check(bool),
+    /// Report problems in the stored history.
+    Audit(quality::Thresholds),
+    /// Reconcile balances against actions and unit values, for a fund or for all of them, reporting residuals larger than a tolerance.
+    Reconcile(Option<String>, Cents),
+    /// List the changes to the stored records, for a fund or for all of them.
+    Changes(Option<String>),
+    /// Revert a change to the stored records, by its number in the list of changes.
+    Undo(usize),
+}
+
+const USAGE: &str = "Usage:
+    fondos [--risk-free-rate <percent>] [--correlation-windows <days>,<days>...] [--real] [--format <png|svg|pdf>] [--on-conflict <overwrite|keep|ask|fail>] [--locale <bank|es-co>]
+    fondos set-currency <fund> <COP|USD>
+    fondos tax-report <year>
+    fondos set-fee <fund> <d/m/y> <annual percent>
+    fondos charge-fee <fund> <d/m/y> <$amount>
+    fondos fees <d/m/y> <d/m/y>
+    fondos serve [<port>]
+    fondos tui
+    fondos show <balances|actions|fund-values> [<filters>]
+    fondos <add|edit|delete> <action|balance|fund-value> <fund> <d/m/y> [<$amounts>] [<options>]
+    fondos pair <fund> <d/m/y> <-$withdrawal> <fund> <d/m/y> <$deposit>
+    fondos check [--all]
+    fondos audit [--max-gap <business days>] [--max-change <percent>]
+    fondos reconcile [<fund>] [--tolerance <$amount>]
+    fondos changes [--fund <fund>]
+    fondos undo <change number>";
+
+/// Options given in the command line.
+struct Options {
+    command: Command,
+    /// Annual risk-free rate for the Sharpe and Sortino ratios, expressed in percentage. For example, the rate of Banco de la República.
+    risk_free_rate: f64,
+    /// Windows for the correlations between funds, expressed in days, up to 100 years.
+    correlation_windows: Vec<i64>,
+    /// Express the variations in fondos00.png in pesos of the first date of each plot, using the consumer price index in ipc.txt.
+    real: bool,
+    /// Format of the figures: PNG, or the vector formats SVG and PDF.
+    figure_format: figure::FigureFormat,
+    /// What to do when balances.txt or profit.txt has a different value for a date that is already stored.
+    overwrite_policy: changelog::OverwritePolicy,
+    /// Format of the amounts and percentages in balances.txt, history.txt, and profit.txt.
+    locale: Locale,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            command: Command::Update,
+            risk_free_rate: 0.,
+            correlation_windows: vec![30, 90, 365],
+            real: false,
+            figure_format: figure::FigureFormat::Png,
+            overwrite_policy: changelog::OverwritePolicy::Overwrite,
+            locale: Locale::BANK,
+        }
+    }
+}
+
+fn parse_options<I>(args: I) -> Result<Options, String>
+where
+I: IntoIterator<Item = String>
+{
+    let mut options = Options::default();
+    let mut args_it = args.into_iter();
+    while let Some(arg) = args_it.next() {
+        match arg.as_str() {
+            "--risk-free-rate" => {
+                let rate = parse_percent(args_it.next().as_deref(), || "Parsing option --risk-free-rate: ".to_string())?;
+                if rate.is_nan() {
+                    return Err("Parsing option --risk-free-rate: The rate must be a number".to_string());
+                }
+                options.risk_free_rate = rate;
+            }
+            "--correlation-windows" => {
+                let windows_str = args_it.next().ok_or("Parsing option --correlation-windows: No list of days")?;
+                options.correlation_windows = windows_str
+                    .split(',')
+                    .map(|w| match w.trim().parse::<i64>() {
+                        Ok(days) if days > 0 && days <= 36500 => Ok(days),
+                        _ => Err(format!("Parsing option --correlation-windows: {} is not a number of days between 1 and 36500", w)),
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            "--real" => options.real = true,
+            "--format" => {
+                let format_str = args_it.next().ok_or("Parsing option --format: No format")?;
+                options.figure_format = format_str.parse().map_err(|e| format!("Parsing option --format: {}", e))?;
+            }
+            "--locale" => {
+                let locale_str = args_it.next().ok_or("Parsing option --locale: No locale")?;
+                options.locale = locale_str.parse().map_err(|e| format!("Parsing option --locale: {}", e))?;
+            }
+            "--on-conflict" => {
+                let policy_str = args_it.next().ok_or("Parsing option --on-conflict: No policy")?;
+                options.overwrite_policy = policy_str.parse().map_err(|e| format!("Parsing option --on-conflict: {}", e))?;
+            }
+            "set-currency" if options.command == Command::Update => {
+                let fund = parse_name(args_it.next().as_deref(), || "Parsing fund name for set-currency: ".to_string())?.to_lowercase();
+                let currency = args_it.next().ok_or("Parsing currency for set-currency: No currency")?.parse()?;
+                options.command = Command::SetCurrency(fund, currency);
+            }
+            "tax-report" if options.command == Command::Update => {
+                let year_str = args_it.next().ok_or("Parsing year for tax-report: No year")?;
+                match year_str.trim().parse::<i32>() {
+                    Ok(year) if (1900..=9999).contains(&year) => options.command = Command::TaxReport(year),
+                    _ => return Err(format!("Parsing year for tax-report: {} is not a valid year", year_str)),
+                }
+            }
+            "set-fee" if options.command == Command::Update => {
+                let fund = parse_name(args_it.next().as_deref(), || "Parsing fund name for set-fee: ".to_string())?.to_lowercase();
+                let date = parse_date_opt(args_it.next().as_deref(), || "Parsing date for set-fee: ".to_string())?;
+                let percent = parse_percent(args_it.next().as_deref(), || "Parsing annual fee for set-fee: ".to_string())?;
+                if !(0. ..100.).contains(&percent) {
+                    return Err("Parsing annual fee for set-fee: The fee must be a percentage between 0 and 100".to_string());
+                }
+                options.command = Command::SetFee(fund, date, (100.0 * percent).round() as i64);
+            }
+            "charge-fee" if options.command == Command::Update => {
+                let fund = parse_name(args_it.next().as_deref(), || "Parsing fund name for charge-fee: ".to_string())?.to_lowercase();
+                let date = parse_date_opt(args_it.next().as_deref(), || "Parsing date for charge-fee: ".to_string())?;
+                let amount = parse_cents(args_it.next().as_deref(), || "Parsing amount for charge-fee: ".to_string())?;
+                options.command = Command::ChargeFee(fund, date, amount);
+            }
+            "fees" if options.command == Command::Update => {
+                let start_date = parse_date_opt(args_it.next().as_deref(), || "Parsing first date for fees: ".to_string())?;
+                let end_date = parse_date_opt(args_it.next().as_deref(), || "Parsing last date for fees: ".to_string())?;
+                if end_date < start_date {
+                    return Err("Parsing dates for fees: The last date precedes the first date".to_string());
+                }
+                options.command = Command::Fees(start_date, end_date);
+            }
+            "tui" if options.command == Command::Update => options.command = Command::Tui,
+            "show" if options.command == Command::Update => options.command = Command::Show(query::parse_query(&mut args_it)?),
+            "add" if options.command == Command::Update => options.command = Command::Correct(edit::parse_correction(edit::Operation::Add, &mut args_it)?),
+            "edit" if options.command == Command::Update => options.command = Command::Correct(edit::parse_correction(edit::Operation::Edit, &mut args_it)?),
+            "delete" if options.command == Command::Update => options.command = Command::Correct(edit::parse_correction(edit::Operation::Delete, &mut args_it)?),
+            "check" if options.command == Command::Update => options.command = Command::Check(false),
+            "--all" if options.command == Command::Check(false) => options.command = Command::Check(true),
+            "audit" if options.command == Command::Update => options.command = Command::Audit(quality::Thresholds::default()),
+            "--max-gap" if matches!(options.command, Command::Audit(_)) => {
+                let gap_str = args_it.next().unwrap_or_default();
+                match (&mut options.command, gap_str.parse::<i64>()) {
+                    (Command::Audit(thresholds), Ok(days)) if days > 0 => thresholds.max_gap = days,
+                    _ => return Err(format!("Parsing option --max-gap: {} is not a positive number of days", gap_str)),
+                }
+            }
+            "--max-change" if matches!(options.command, Command::Audit(_)) => {
+                let change = parse_percent(args_it.next().as_deref(), || "Parsing option --max-change: ".to_string())?;
+                if let Command::Audit(thresholds) = &mut options.command {
+                    if change.is_nan() || change <= 0. {
+                        return Err("Parsing option --max-change: The change must be a positive number".to_string());
+                    }
+                    thresholds.max_change = change;
+                }
+            }
+            "reconcile" if options.command == Command::Update => options.command = Command::Reconcile(None, 10000),
+            "--tolerance" if matches!(options.command, Command::Reconcile(..)) => {
+                let tolerance = parse_cents(args_it.next().as_deref(), || "Parsing option --tolerance for reconcile: ".to_string())?;
+                if let Command::Reconcile(_, t) = &mut options.command {
+                    *t = tolerance;
+                }
+            }
+            fund if matches!(options.command, Command::Reconcile(None, _)) => {
+                if let Command::Reconcile(f, _) = &mut options.command {
+                    *f = Some(fund.trim().to_lowercase());
+                }
+            }
+            "changes" if options.command == Command::Update => options.command = Command::Changes(None),
+            "--fund" if options.command == Command::Changes(None) => {
+                let fund = parse_name(args_it.next().as_deref(), || "Parsing option --fund for changes: ".to_string())?.to_lowercase();
+                options.command = Command::Changes(Some(fund));
+            }
+            "undo" if options.command == Command::Update => {
+                let number_str = args_it.next().unwrap_or_default();
+                match number_str.trim().parse::<usize>() {
+                    Ok(number) if number > 0 => options.command = Command::Undo(number),
+                    _ => return Err(format!("Parsing change number for undo: {} is not a change number. Usage:\n    {}", number_str, changelog::CHANGES_USAGE)),
+                }
+            }
+            "pair" if options.command == Command::Update => {
+                let (out, into) = transfer::parse_pair(&mut args_it)?;
+                options.command = Command::Pair(out, into);
+            }
+            "serve" if options.command == Command::Update => {
+                options.command = Command::Serve(8080);
+            }
+            port_str if matches!(options.command, Command::Serve(_)) => match port_str.parse::<u16>() {
+                Ok(port) if port > 0 => options.command = Command::Serve(port),
+                _ => return Err(format!("Parsing port for serve: {} is not a valid port", port_str)),
+            },
+            _ => return Err(format!("Unknown option {}. {}", arg, USAGE)),
+        }
+    }
+    Ok(options)
 }
 
 fn calculate_hash<T: std::hash::Hash>(t: &T) -> u64 {
@@ -163,6 +466,26 @@ fn calculate_hash<T: std::hash::Hash>(t: &T) -> u64 {
     hasher.finish()
 }
 
+/// Latest movements of the open funds, as the text of comparison.csv.
+fn comparison_csv(table: &Table) -> String {
+    let mut text = "Fund,Previous date,Previous $,Change,Last date,Last $\n".to_string();
+    for series in table.open_series() {
+        let mut it = series.balance.iter().rev();
+        if let Some(last_record) = it.next() {
+            text.push_str(&series.fund);
+            let last_record_balance = last_record.balance as f64 / 100.0;
+            if let Some(next_to_last_record) = it.next() {
+                let next_to_last_record_balance = next_to_last_record.balance as f64 / 100.0;
+                text.push_str(&format!(",{},{},{}", next_to_last_record.date, next_to_last_record_balance, last_record_balance - next_to_last_record_balance));
+            } else {
+                text.push_str(",,,");
+            }
+            text.push_str(&format!(",{},{}\n", last_record.date, last_record_balance));
+        }
+    }
+    text
+}
+
 fn create_file(file_name: &str) -> Result<std::fs::File, String> {
     use {std::fs::File, std::path::Path};
     let path = Path::new(&file_name);
@@ -214,26 +537,74 @@ F: Fn() -> String
     parse_date(ok_date, error_prefix)
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Conventions for writing amounts and percentages in the files of the bank.
+struct Locale {
+    thousands_separator: char,
+    decimal_separator: char,
+    /// Whether the $ sign follows the amount, as in 1.234,56 $.
+    symbol_after: bool,
+    /// Whether amounts in parentheses, as in ($1,234.56), are negative.
+    parentheses_negative: bool,
+    /// Whether amounts without decimal digits, as in $1,234, are accepted as whole pesos.
+    optional_decimals: bool,
+}
+
+impl Locale {
+    /// Format of the exports of the bank, such as $1,234.56 and 12.5 %.
+    const BANK: Locale = Locale { thousands_separator: ',', decimal_separator: '.', symbol_after: false, parentheses_negative: false, optional_decimals: false };
+    /// Format of Colombian documents, such as $ 1.234.567,89, ($ 1.234) and 12,5 %.
+    const COLOMBIA: Locale = Locale { thousands_separator: '.', decimal_separator: ',', symbol_after: false, parentheses_negative: true, optional_decimals: true };
+}
+
+impl std::str::FromStr for Locale {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "bank" => Ok(Locale::BANK),
+            "es-co" => Ok(Locale::COLOMBIA),
+            _ => Err(format!("Locale {} not recognized; use bank or es-co", s.trim())),
+        }
+    }
+}
+
+/// Strips the parentheses of a negative value, if the locale has them. Returns whether the value is negative.
+fn strip_parentheses<'a>(value: &'a str, locale: &Locale) -> (bool, &'a str) {
+    match value.strip_prefix('(').and_then(|v| v.strip_suffix(')')) {
+        Some(inner) if locale.parentheses_negative => (true, inner.trim()),
+        _ => (false, value),
+    }
+}
+
 fn parse_cents<F>(pesos_opt: Option<&str>, error_prefix: F) -> Result<Cents, String>
 where
 F: Fn() -> String
+{
+    parse_cents_locale(pesos_opt, &Locale::BANK, error_prefix)
+}
+
+fn parse_cents_locale<F>(pesos_opt: Option<&str>, locale: &Locale, error_prefix: F) -> Result<Cents, String>
+where
+F: Fn() -> String
 {
     let trimmed_pesos = pesos_opt.ok_or_else(|| format!("{}No valid amount", error_prefix()))?.trim();
     let err = |msg| Err(format!("{}{}. Is the value {} correctly formatted as pesos?", error_prefix(), msg, trimmed_pesos));
-    let len = trimmed_pesos.len(); // Shortest value: "$.00" "$000.00"
-    if len < 4 {
+    let len = trimmed_pesos.len(); // Shortest value: "$.00" "$000.00", or "$0" without decimals
+    if len < if locale.optional_decimals { 2 } else { 4 } {
         return err("Pesos value too short");
     }
+    let (negative, unsigned_pesos) = strip_parentheses(trimmed_pesos, locale);
+    let number = match if locale.symbol_after { unsigned_pesos.strip_suffix('$').map(str::trim_end) } else { unsigned_pesos.strip_prefix('$').map(str::trim_start) } {
+        Some(number) => number,
+        None => return err("Value has no $ sign"),
+    };
     let mut cents_str = String::new();
-    let mut pesos_it = trimmed_pesos.chars();
-    if pesos_it.next() != Some('$') {
-        return err("Value has no $ sign");
-    }
+    let mut pesos_it = number.chars();
     let mut comma_digits = -1;
     let mut decimal_digits = -1;
     let mut largest_digits = 0;
     match pesos_it.next() {
-        Some('.') => {
+        Some(c) if c == locale.decimal_separator => {
             decimal_digits = 0;
         },
         Some(c) if c.is_digit(10) => {
@@ -241,7 +612,7 @@ F: Fn() -> String
             largest_digits += 1;
         },
         Some(c) => {
-            return err(&format!("Pesos value starts with ${} instead of a number", c));
+            return err(&format!("Pesos value starts with {}{} instead of a number", if locale.symbol_after { "" } else { "$" }, c));
         }
         None => {
             return err("Pesos value is cut short");
@@ -252,13 +623,13 @@ F: Fn() -> String
         if decimal_digits < 0 {
             if comma_digits < 0 {
                 match c {
-                    '.' => decimal_digits = 0,
-                    ',' => comma_digits = 0,
+                    c if c == locale.decimal_separator => decimal_digits = 0,
+                    c if c == locale.thousands_separator => comma_digits = 0,
                     '0'..='9' => {
                         cents_str.push(c);
                         largest_digits += 1;
                         if largest_digits > 3 {
-                            return err("Pesos value has too many digits before the first ','");
+                            return err(&format!("Pesos value has too many digits before the first '{}'", locale.thousands_separator));
                         }
                     },
                     _ => return err(&format!("Pesos value has invalid character '{}'", c)),
@@ -267,10 +638,10 @@ F: Fn() -> String
                 comma_digits += 1;
                 comma_digits %= 4;
                 if comma_digits == 0 {
-                    if c == '.' {
+                    if c == locale.decimal_separator {
                         decimal_digits = 0;
-                    } else if c != ',' {
-                        return err(&format!("Pesos value has '{}' instead of the ',' thousands separator", c));
+                    } else if c != locale.thousands_separator {
+                        return err(&format!("Pesos value has '{}' instead of the '{}' thousands separator", c, locale.thousands_separator));
                     }
                 } else {
                     if c.is_digit(10) {
@@ -289,39 +660,99 @@ F: Fn() -> String
             }
         }
     }
-    if decimal_digits == 1 {
+    if decimal_digits < 0 && locale.optional_decimals {
+        if (0..3).contains(&comma_digits) {
+            return err(&format!("Pesos value has {} digits after the last '{}' instead of 3", comma_digits, locale.thousands_separator));
+        }
+        cents_str.push_str("00");
+    } else if decimal_digits < 0 {
+        return err(&format!("Pesos value has no '{}' and 2 decimal digits", locale.decimal_separator));
+    } else if decimal_digits == 1 {
         return err("Pesos value has 1 decimal digit instead of 2");
-    }
-    if decimal_digits != 2 {
+    } else if decimal_digits != 2 {
         return err(&format!("Pesos value has {} decimal digits instead of 2", decimal_digits));
     }
-    cents_str.parse::<i64>().map_err(|e| e.to_string())
+    cents_str.parse::<i64>().map(|c| if negative { -c } else { c }).map_err(|e| e.to_string())
 }
 
+/// Formats cents as pesos, such as $1,234.56 or -$1,234.56.
+fn format_cents(cents: Cents) -> String {
+    let pesos = (cents / 100).abs().to_string();
+    let mut grouped = String::new();
+    for (i, c) in pesos.chars().enumerate() {
+        if i > 0 && (pesos.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    format!("{}${}.{:02}", if cents < 0 { "-" } else { "" }, grouped, (cents % 100).abs())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A percentage reported by the bank, in fixed point, so that it is written back as reported.
+enum Percent {
+    /// Value in units of the last decimal digit, and the number of decimal digits; -0.003 % is Value(-3, 3).
+    Value(i64, u32),
+    /// Reported as NA.
+    NotAvailable,
+}
+
+impl Percent {
+    /// Value in percentage; NaN if not available.
+    fn to_f64(self) -> f64 {
+        match self {
+            Percent::Value(units, decimals) => units as f64 / 10f64.powi(decimals as i32),
+            Percent::NotAvailable => f64::NAN,
+        }
+    }
+}
+
+impl std::fmt::Display for Percent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Percent::Value(units, 0) => write!(f, "{}", units),
+            Percent::Value(units, decimals) => {
+                let scale = 10i64.pow(decimals);
+                write!(f, "{}{}.{:0width$}", if units < 0 { "-" } else { "" }, (units / scale).abs(), (units % scale).abs(), width = decimals as usize)
+            }
+            Percent::NotAvailable => write!(f, "NA"),
+        }
+    }
+}
+
+/// Parses a percentage given in the command line, such as 1.75; NA becomes NaN.
 fn parse_percent<F>(percent_opt: Option<&str>, error_prefix: F) -> Result<f64, String>
 where
 F: Fn() -> String
+{
+    parse_percent_locale(percent_opt, &Locale::BANK, error_prefix).map(Percent::to_f64)
+}
+
+fn parse_percent_locale<F>(percent_opt: Option<&str>, locale: &Locale, error_prefix: F) -> Result<Percent, String>
+where
+F: Fn() -> String
 {
     let trimmed_percent = percent_opt.ok_or_else(|| format!("{}No valid amount", error_prefix()))?.trim();
     let err = |msg| Err(format!("{}{}. Is the value {} correctly formatted as a percentage?", error_prefix(), msg, trimmed_percent));
     if trimmed_percent == "NA" {
-        return Ok(f64::NAN);
+        return Ok(Percent::NotAvailable);
     }
     let len = trimmed_percent.len(); // Shortest value: "$.00" "$000.00"
     if len < 3 {
         return err("Percent value too short");
     }
-    let mut percent_str = String::new();
-    let mut percent_it = trimmed_percent.chars();
+    let (negative, unsigned_percent) = strip_parentheses(trimmed_percent, locale);
+    let mut percent_str = String::from(if negative { "-" } else { "" });
+    let mut percent_it = unsigned_percent.chars();
     let mut comma_digits = -1;
     let mut decimal_digits = -1;
     let mut largest_digits = 0;
     match percent_it.next() {
-        Some('.') => {
+        Some(c) if c == locale.decimal_separator => {
             percent_str.push('.');
             decimal_digits = 0;
         },
-        Some('-') => {
+        Some('-') if !negative => {
             percent_str.push('-');
         },
         Some(c) if c.is_digit(10) => {
@@ -340,11 +771,11 @@ F: Fn() -> String
         if decimal_digits < 0 {
             if comma_digits < 0 {
                 match c {
-                    '.' => {
+                    c if c == locale.decimal_separator => {
                         percent_str.push('.');
                         decimal_digits = 0
                     },
-                    ',' => comma_digits = 0,
+                    c if c == locale.thousands_separator => comma_digits = 0,
                     ' ' | '%' => {
                         break;
                     }
@@ -352,7 +783,7 @@ F: Fn() -> String
                         percent_str.push(c);
                         largest_digits += 1;
                         if largest_digits > 3 {
-                            return err("Percentage has too many digits before the first ','");
+                            return err(&format!("Percentage has too many digits before the first '{}'", locale.thousands_separator));
                         }
                     },
                     _ => return err(&format!("Percentage has invalid character '{}'", c)),
@@ -362,11 +793,11 @@ F: Fn() -> String
                 comma_digits %= 4;
                 if comma_digits == 0 {
                     match c {
-                        '.' => {
+                        c if c == locale.decimal_separator => {
                             percent_str.push('.');
                             decimal_digits = 0;
                         }
-                        ',' => {}
+                        c if c == locale.thousands_separator => {}
                         ' ' | '%' => {
                             break;
                         }
@@ -396,7 +827,8 @@ F: Fn() -> String
             }
         }
     }
-    percent_str.parse::<f64>().map_err(|e| e.to_string())
+    let decimals = percent_str.find('.').map_or(0, |i| percent_str.len() - i - 1) as u32;
+    percent_str.replace('.', "").parse::<i64>().map(|units| Percent::Value(units, decimals)).map_err(|e| e.to_string())
 }
 
 fn columns(n_durations: usize) -> usize {
@@ -406,62 +838,183 @@ fn columns(n_durations: usize) -> usize {
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     use plotters::prelude::*;
     use std::fs;
+    let options = parse_options(std::env::args().skip(1))?;
     let date = chrono::Local::today().naive_local();
     let funds_file_name = "data/funds.dat";
-    let r_err0 = |e| Err(format!("Error reading the file {}: {}", funds_file_name, e));
-    let r_err1 = |e| Err(format!("Error reading the file {}: {}", funds_file_name, e)); // Two closures with similar name; they differ in the type of e. Reminder: Rust does not define generic closures.
-    let w_err0 = |e| Err(format!("Error writing to file {}: {}", funds_file_name, e));
-    let db_path = std::path::Path::new(&funds_file_name);
-    let mut table: Table = if db_path.exists() {
-        let db_file = fs::File::open(db_path).or_else(r_err0)?;
-        bincode::deserialize_from(db_file).or_else(r_err1)?
-    } else {
-        println!("Starting a new file. Ctrl + C if this is a mistake.");
-        Table {
-            table: Vec::<_>::with_capacity(10),
-            investor: vec!["Default investor".to_string(), "Investor 1".to_string()],
-        }
-    };
+    let change_log_file_name = "data/audit.txt";
+    if let Command::Serve(port) = options.command {
+        serve::serve(funds_file_name, port)?;
+        return Ok(());
+    }
+    let mut table = storage::load(funds_file_name)?;
     let original_hash = calculate_hash(&table);
     table.table.iter_mut().for_each(|s| s.fund = s.fund.trim().to_lowercase());
+    if let Command::SetCurrency(fund, currency) = &options.command {
+        let series = table.table.iter_mut().find(|s| s.fund == *fund).ok_or_else(|| format!("Fund {} not found", fund))?;
+        let before = series.clone();
+        series.currency = *currency;
+        let changes = changelog::diff(&before, series, chrono::Local::now().naive_local(), "command line", "command set-currency");
+        storage::save(&table, funds_file_name)?;
+        changelog::append(change_log_file_name, &changes)?;
+        println!("Fund {} now has currency {}.", fund, currency);
+        return Ok(());
+    }
+    if let Command::TaxReport(year) = options.command {
+        let records = tax::tax_report(&table, year);
+        let csv_file_name = format!("tax_report_{}.csv", year);
+        tax::write_csv(&records, &csv_file_name)?;
+        print!("{}", tax::summary(&records, year));
+        println!("\nThe figures are saved to {}. Taxable income is an estimate; please check it against the certificates of the bank.", csv_file_name);
+        return Ok(());
+    }
+    if let Command::SetFee(fund, fee_date, annual_fee) = &options.command {
+        let series = table.table.iter_mut().find(|s| s.fund == *fund).ok_or_else(|| format!("Fund {} not found", fund))?;
+        let before = series.clone();
+        series.fee.retain(|f| f.date != *fee_date);
+        series.fee.push(Fee { date: *fee_date, annual_fee: *annual_fee });
+        series.fee.sort_unstable();
+        let changes = changelog::diff(&before, series, chrono::Local::now().naive_local(), "command line", "command set-fee");
+        storage::save(&table, funds_file_name)?;
+        changelog::append(change_log_file_name, &changes)?;
+        println!("Fund {} now has an annual fee of {}% from {}.", fund, *annual_fee as f64 / 100.0, fee_date);
+        return Ok(());
+    }
+    if let Command::ChargeFee(fund, charge_date, amount) = &options.command {
+        let series = table.table.iter_mut().find(|s| s.fund == *fund).ok_or_else(|| format!("Fund {} not found", fund))?;
+        let before = series.clone();
+        series.action.push(Action { date: *charge_date, change: -amount, investor: 0, kind: ActionKind::FeeCharge, source: None, provenance: None });
+        series.action.sort_unstable();
+        let changes = changelog::diff(&before, series, chrono::Local::now().naive_local(), "command line", "command charge-fee");
+        storage::save(&table, funds_file_name)?;
+        changelog::append(change_log_file_name, &changes)?;
+        println!("Fund {} now has a fee charge of {} on {}.", fund, format_cents(*amount), charge_date);
+        return Ok(());
+    }
+    if let Command::Fees(start_date, end_date) = options.command {
+        let costs: Vec<_> = table.table.iter().filter(|s| !s.balance.is_empty()).map(|s| fees::cost(s, start_date, end_date)).collect();
+        println!("Costos de los fondos del {} al {}\n", start_date, end_date);
+        print!("{}", fees::summary(&costs));
+        if table.table.iter().all(|s| s.fee.is_empty()) {
+            println!("\nNo fund has a fee schedule; estimated fees are zero. Use fondos set-fee to set one.");
+        }
+        return Ok(());
+    }
 
-    // A few examples useful for debugging
-    // table.table.iter().find(|s| s.fund == "capital").unwrap().action.iter().enumerate().for_each(|r| println!("{:?}", r));
-    // table.table.iter().find(|s| s.fund == "consumo global").unwrap().balance.iter().enumerate().for_each(|r| println!("{:?}", r));
+    if let Command::Show(query) = &options.command {
+        print!("{}", query.run(&table)?);
+        return Ok(());
+    }
+    if options.command == Command::Tui {
+        tui::run(table, date)?;
+        return Ok(());
+    }
 
-    // It is usual to transfer all money from one fund to another.
-    // In those cases, the emptied fund disappears from balances.txt and history.txt.
-    // We must manually account for this disappearance.
-    // {
-    //     let emptied_fund = &mut table.table.iter_mut().find(|s| s.fund == "consumo global").unwrap();
-    //     let date = chrono::NaiveDate::from_ymd(2021, 11, 19);
-    //     emptied_fund.action.push(Action {
-    //         date,
-    //         change: -321686700,
-    //     });
-    //     emptied_fund.action.push(Action {
-    //         date,
-    //         change: -1209632,
-    //     });
-    //     let date = date.succ();
-    //     match emptied_fund.balance.iter_mut().find(|b| b.date == date) {
-    //         Some(b) => {
-    //             b.balance = 0;
-    //         }
-    //         None => {
-    //             emptied_fund.balance.push(Balance {
-    //                 date,
-    //                 balance: 0,
-    //             });
-    //         }
-    //     }
-    // }
+    if let Command::Correct(correction) = &options.command {
+        let (description, changes) = changelog::apply(correction, &mut table, date, "manual correction")?;
+        storage::save(&table, funds_file_name)?;
+        changelog::append(change_log_file_name, &changes)?;
+        println!("Fund {}: {}. The change is recorded in {}.", correction.fund, description, change_log_file_name);
+        return Ok(());
+    }
+    if let Command::Pair(out, into) = &options.command {
+        let description = transfer::confirm(&mut table, out.clone(), into.clone())?;
+        storage::save(&table, funds_file_name)?;
+        let change = changelog::Change {
+            time: chrono::Local::now().naive_local(),
+            fund: out.fund.clone(),
+            record: "transfer".to_string(),
+            date: Some(out.date),
+            old: Vec::new(),
+            new: Vec::new(),
+            source: "command line".to_string(),
+            reason: format!("confirmed {}", description),
+        };
+        changelog::append(change_log_file_name, &[change])?;
+        println!("Confirmed {}. The change is recorded in {}.", description, change_log_file_name);
+        return Ok(());
+    }
+    if let Command::Changes(fund) = &options.command {
+        let changes = changelog::read(change_log_file_name)?;
+        print!("{}", changelog::list(&changes, fund.as_deref()));
+        println!("{} changes are recorded in {}. Run fondos undo <change number> to revert one.", changes.len(), change_log_file_name);
+        return Ok(());
+    }
+    if let Command::Undo(number) = options.command {
+        let changes = changelog::read(change_log_file_name)?;
+        let change = changes.get(number - 1).ok_or_else(|| format!("Change {} not found; {} changes are recorded in {}", number, changes.len(), change_log_file_name))?;
+        let correction = changelog::undo(change, number, &table)?;
+        let (description, changes) = changelog::apply(&correction, &mut table, date, &format!("undo of change {}", number))?;
+        storage::save(&table, funds_file_name)?;
+        changelog::append(change_log_file_name, &changes)?;
+        println!("Fund {}: {}. The change is recorded in {}.", correction.fund, description, change_log_file_name);
+        return Ok(());
+    }
+    if let Command::Audit(thresholds) = &options.command {
+        table.table.iter_mut().for_each(|series| {
+            series.balance.sort_unstable();
+            series.action.sort_unstable();
+            series.fund_value.sort_unstable();
+        });
+        let findings = quality::audit(&table, thresholds);
+        for finding in findings.iter() {
+            println!("{}", finding);
+        }
+        println!("{} findings in {} funds.", findings.len(), table.table.len());
+        return Ok(());
+    }
+    if let Command::Reconcile(fund, tolerance) = &options.command {
+        if let Some(f) = fund {
+            table.table.iter().find(|s| s.fund == *f).ok_or_else(|| format!("Fund {} not found", f))?;
+        }
+        table.table.iter_mut().for_each(|series| {
+            series.balance.sort_unstable();
+            series.action.sort_unstable();
+            series.fund_value.sort_unstable();
+        });
+        let reconciliations = reconcile::reconcile(&table, fund.as_deref());
+        let csv_file_name = "reconciliation.csv";
+        reconcile::write_csv(&reconciliations, csv_file_name)?;
+        print!("{}", reconcile::summary(&reconciliations, *tolerance));
+        println!("\nEvery period is saved to {}.", csv_file_name);
+        return Ok(());
+    }
+    if let Command::Check(all) = options.command {
+        let unmatched = transfer::check(&mut table, all);
+        for u in unmatched.iter() {
+            println!("{}", u);
+        }
+        println!("{} withdrawals and deposits without a pair. {} pairs are stored.", unmatched.len(), table.transfer.len());
+        if calculate_hash(&table) != original_hash {
+            storage::save(&table, funds_file_name)?;
+        }
+        return Ok(());
+    }
+
+    // Optional inputs of the update and the report, read before importing so that a bad file changes nothing
+    let cpi_file_name = "ipc.txt";
+    let cpi = inflation::Cpi::read(cpi_file_name)?;
+    if options.real && cpi.is_none() {
+        return Err(format!("Option --real needs the consumer price index in file {}", cpi_file_name).into());
+    }
+    let exchange_rate_file_name = "trm.txt";
+    let exchange_rate = currency::ExchangeRate::read(exchange_rate_file_name)?;
+    let benchmarks = benchmark::read_benchmarks("benchmarks", date)?;
+    let benchmark_assignments = benchmark::read_assignments("benchmarks/benchmarks.txt")?;
+    for (fund, benchmark_name) in benchmark_assignments.iter() {
+        if !benchmarks.iter().any(|b| b.fund == *benchmark_name) {
+            return Err(format!("Fund {} is assigned benchmark {}, but file benchmarks/{}.csv does not exist", fund, benchmark_name, benchmark_name).into());
+        }
+    }
     let mut table_aggregate: Vec<FundAggregate> = Vec::new();
+    // Changes to stored records, for the change log
+    let mut changes = Vec::new();
+    let now = chrono::Local::now().naive_local();
     // Process balances.txt
     {
         let mut mode = Mode::Header;
         let mut input_lines = Vec::new();
         let mut fund_data_status = BalancesTxtStatus::NoData;
+        let import = provenance::register(&mut table, "balances.txt")?;
         for (line_index, input_res) in file_lines("balances.txt")?.enumerate() {
             let input = input_res?;
             match mode {
@@ -484,39 +1037,78 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         Some(date_str) => {
                             let date = parse_date(date_str, || format!("Parsing date at balances.txt line {}: ", line_index + 1))?;
                             fund_data_status = BalancesTxtStatus::Processed;
+                            let mut snapshot = Vec::with_capacity(input_lines.len());
                             for (line_index, input) in input_lines.into_iter() {
                                 let mut fields = input.split('\t');
                                 let fund_name = parse_name(fields.next(), || format!("Parsing fund name at balances.txt line {} field 1: ", line_index + 1))?.to_lowercase();
-                                let balance = parse_cents(fields.next(), || format!("Parsing {} fund balance at balances.txt line {} field 2: ", fund_name, line_index + 1))?;
+                                let balance = parse_cents_locale(fields.next(), &options.locale, || format!("Parsing {} fund balance at balances.txt line {} field 2: ", fund_name, line_index + 1))?;
                                 assert_eq!(fields.count(), 4); // 4 remaining fields, to be left unused
+                                let provenance = Some(provenance::Provenance { import, line: line_index as u32 + 1 });
+                                snapshot.push(fund_name.clone());
                                 match table.table.iter_mut().find(|s| s.fund == fund_name) {
                                     Some(series) => {
+                                        if let Some(closed) = series.closed.take() {
+                                            println!("Fund {}, closed on {}, is in balances.txt again; it is open now.", fund_name, closed);
+                                        }
                                         match series
                                             .balance
                                             .iter_mut()
                                             .find(|b: &&mut Balance| b.date == date)
                                         {
-                                            Some(b) => {
-                                                if b.balance != balance {
-                                                    println!("Warning: Fund changing balance from {} to {}", b.balance, balance);
+                                            Some(b) if b.balance != balance => {
+                                                let warning = format!("Warning: Fund {} balance on {} changes from {} in balances.txt line {}", fund_name, date, changelog::difference(b.balance, balance), line_index + 1);
+                                                if options.overwrite_policy.overwrite(&warning)? {
+                                                    changes.push(changelog::Change {
+                                                        time: now,
+                                                        fund: fund_name.clone(),
+                                                        record: "balance".to_string(),
+                                                        date: Some(date),
+                                                        old: vec![format_cents(b.balance)],
+                                                        new: vec![format_cents(balance)],
+                                                        source: format!("balances.txt line {}", line_index + 1),
+                                                        reason: "balances.txt has a different balance".to_string(),
+                                                    });
                                                     b.balance = balance;
+                                                    b.provenance = provenance;
                                                 }
                                             }
+                                            Some(_) => {}
                                             None => {
-                                                series.balance.push(Balance { date, balance })
+                                                series.balance.push(Balance { date, balance, provenance })
                                             }
                                         }
                                     }
                                     None => {
                                         table.table.push(Series {
                                             fund: String::from(fund_name),
-                                            balance: vec![Balance { date, balance }],
+                                            balance: vec![Balance { date, balance, provenance }],
                                             action: Vec::<_>::with_capacity(10),
                                             fund_value: Vec::<_>::with_capacity(10),
+                                            currency: Currency::Cop,
+                                            fee: Vec::new(),
+                                            closed: None,
                                         });
                                     }
                                 }
                             }
+                            // It is usual to transfer all money from one fund to another.
+                            // In those cases, the emptied fund disappears from balances.txt and history.txt.
+                            for (fund_index, last_balance) in edit::missing_funds(&table, &snapshot, date) {
+                                let series = &mut table.table[fund_index];
+                                print!(
+                                    "Fund {} had a balance of {} on {}, but it is missing from balances.txt of {}. Record the withdrawal of that balance and a zero balance, and close the fund? [y/N] ",
+                                    series.fund, format_cents(last_balance.balance), last_balance.date, date
+                                );
+                                std::io::stdout().flush()?;
+                                let mut answer = String::new();
+                                std::io::stdin().read_line(&mut answer)?;
+                                if matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+                                    let before = series.clone();
+                                    let description = edit::close_fund(series, &last_balance, date);
+                                    changes.extend(changelog::diff(&before, series, now, "balances.txt", &format!("missing from balances.txt of {}; closed after confirmation", date)));
+                                    println!("Fund {}: {}.", series.fund, description);
+                                }
+                            }
                             break; // Stop reading the file
                         },
                         None => {},
@@ -528,9 +1120,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
     // Process history.txt
     {
-        let mut repetitions = Vec::<Repetition>::with_capacity(10);
+        let history_file_name = "history.txt";
+        let mut history_lines = Vec::new();
         let mut skip_header = true;
-        for (line_index, input_res) in file_lines("history.txt")?.enumerate() {
+        let import = provenance::register(&mut table, history_file_name)?;
+        for (line_index, input_res) in file_lines(history_file_name)?.enumerate() {
             let input = input_res?;
             if skip_header {
                 if input.starts_with("Fecha	Nombre del ") {
@@ -544,7 +1138,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let fund_name = parse_name(fields.next(), || format!("Parsing fund name at balances.txt line {} field 2: ", line_index + 1))?.to_lowercase();
                 let action_str = parse_name(fields.next(), || format!("Parsing event description at balances.txt line {} field 3: ", line_index + 1))?;
                 let _unused_str = parse_name(fields.next(), || format!("Parsing event type at balances.txt line {} field 4: ", line_index + 1))?;
-                let change_abs = parse_cents(fields.next(), || format!("Parsing {} fund balance at balances.txt line {} field 5: ", fund_name, line_index + 1))?;
+                let change_abs = parse_cents_locale(fields.next(), &options.locale, || format!("Parsing {} fund balance at balances.txt line {} field 5: ", fund_name, line_index + 1))?;
+                if change_abs < 0 {
+                    // The description gives the sign; an amount in parentheses would turn a withdrawal into a deposit
+                    return Err(format!("Parsing {} amount at {} line {} field 5: The amount {} is negative; history.txt gives amounts without sign", fund_name, history_file_name, line_index + 1, format_cents(change_abs)).into());
+                }
                 assert_eq!(fields.count(), 0); // 0 remaining fields
                 let change = match action_str {
                     "Aporte" | "Aporte por traslado de otro portafolio" => {
@@ -557,60 +1155,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         return Err(Box::new(Error::new(ErrorKind::Other, format!("error code KevkgKt9: Action '{}' not recognized", action_str))));
                     }
                 };
-                match table.table.iter().position(|s| s.fund == fund_name) {
-                    Some(fund_index) => {
-                        match table.table[fund_index].action.iter().position(
-                            |a: &Action| a.date == date && a.change == change,
-                        ) {
-                            Some(action_index) => {
-                                // This can happen frequently. See if this has been counted before.
-                                match repetitions.iter_mut().find(
-                                    |r: &&mut Repetition| {
-                                        r.fund_index == fund_index
-                                            && r.action_index == action_index
-                                    },
-                                ) {
-                                    Some(r) => r.repetition += 1,
-                                    None => repetitions.push(Repetition {
-                                        // This push is valid for both completely new actions as well as actions that are already repeated
-                                        fund_index,
-                                        action_index,
-                                        repetition: 1,
-                                    }),
-                                };
-                            }
-                            None => table.table[fund_index]
-                                .action
-                                .push(Action { date, change, investor: 0 }),
-                        }
-                    }
-                    None => {
-                        table.table.push(Series {
-                            fund: String::from(fund_name),
-                            balance: vec![],
-                            action: vec![Action { date, change, investor: 0 }],
-                            fund_value: Vec::<_>::with_capacity(10),
-                        });
-                    }
-                };
+                let provenance = Some(provenance::Provenance { import, line: line_index as u32 + 1 });
+                history_lines.push(history::HistoryLine { fund: fund_name, date, change, line: input.trim_end().to_string(), provenance });
             }
         }
-        for r in repetitions {
-            let action = &mut table.table[r.fund_index].action;
-            let a = action[r.action_index].clone();
-            let reps = r.repetition
-                - action
-                    .iter()
-                    .filter(|b| a.date == b.date && a.change == b.change)
-                    .count() as i32;
-            for _repetition in 0..reps {
-                action.push(a.clone());
-            }
+        for dropped in history::import(&mut table, history_file_name, history_lines) {
+            println!("{}", dropped);
         }
     }
     // Process profit.txt
     {
         let mut mode = Mode1::Header;
+        let import = provenance::register(&mut table, "profit.txt")?;
         for (line_index, input_res) in file_lines("profit.txt")?.enumerate() {
             let input = input_res?;
             match mode {
@@ -630,15 +1186,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     let mut fields = input.split('\t');
                     let fund_name = parse_name(fields.next(), || format!("Parsing fund name at profit.txt line {} field 1: ", line_index + 1))?.to_lowercase();
                     let date = parse_date_opt(fields.next(), || format!("Parsing date at profit.txt line {} field 2: ", line_index + 1))?;
-                    let fund_value = parse_cents(fields.next(), || format!("Parsing {} fund value at profit.txt line {} field 3: ", fund_name, line_index + 1))?;
-                    let unit_value = parse_cents(fields.next(), || format!("Parsing {} unit value at profit.txt line {} field 4: ", fund_name, line_index + 1))?;
-                    let zero_value = parse_name(fields.next(), || format!("Parsing {} a zero value at profit.txt line {} field 5: ", fund_name, line_index + 1))?;
-                    assert_eq!(zero_value, "$.00");
+                    let fund_value = parse_cents_locale(fields.next(), &options.locale, || format!("Parsing {} fund value at profit.txt line {} field 3: ", fund_name, line_index + 1))?;
+                    let unit_value = parse_cents_locale(fields.next(), &options.locale, || format!("Parsing {} unit value at profit.txt line {} field 4: ", fund_name, line_index + 1))?;
+                    let zero_value = parse_cents_locale(fields.next(), &options.locale, || format!("Parsing {} a zero value at profit.txt line {} field 5: ", fund_name, line_index + 1))?;
+                    if zero_value != 0 {
+                        return Err(format!("Parsing {} a zero value at profit.txt line {} field 5: {} is not zero", fund_name, line_index + 1, format_cents(zero_value)).into());
+                    }
                     assert_eq!(parse_name(fields.next(), || format!("Parsing a null value for {} at profit.txt line {} field 6: ", fund_name, line_index + 1)), Err(format!("Parsing a null value for {} at profit.txt line {} field 6: Empty name", fund_name, line_index + 1)));
-                    let roe_next_to_last_year = parse_percent(fields.next(), || format!("Parsing {} returns from 2 years ago at profit.txt line {} field 7: ", fund_name, line_index + 1))?;
-                    let roe_last_year = parse_percent(fields.next(), || format!("Parsing {} returns from last year at profit.txt line {} field 8: ", fund_name, line_index + 1))?;
-                    let roe_year_to_date = parse_percent(fields.next(), || format!("Parsing {} returns from year to date at profit.txt line {} field 9: ", fund_name, line_index + 1))?;
+                    let roe_next_to_last_year = parse_percent_locale(fields.next(), &options.locale, || format!("Parsing {} returns from 2 years ago at profit.txt line {} field 7: ", fund_name, line_index + 1))?;
+                    let roe_last_year = parse_percent_locale(fields.next(), &options.locale, || format!("Parsing {} returns from last year at profit.txt line {} field 8: ", fund_name, line_index + 1))?;
+                    let roe_year_to_date = parse_percent_locale(fields.next(), &options.locale, || format!("Parsing {} returns from year to date at profit.txt line {} field 9: ", fund_name, line_index + 1))?;
                     assert_eq!(fields.count(), 0); // 0 remaining fields
+                    let provenance = Some(provenance::Provenance { import, line: line_index as u32 + 1 });
                     match table.table.iter_mut().find(|s| s.fund == fund_name) {
                         Some(series) => {
                             match series
@@ -646,21 +1205,38 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                 .iter_mut()
                                 .find(|u: &&mut FundValue| u.date == date)
                             {
-                                Some(x) => {
+                                Some(x) if (x.fund_value, x.unit_value) != (fund_value, unit_value) => {
+                                    let mut differences = Vec::new();
                                     if x.fund_value != fund_value {
-                                        println!("Warning nwSSqjjY: Fund {} changing fund_value from {} to {}", fund_name, x.fund_value, fund_value);
-                                        x.fund_value = fund_value;
+                                        differences.push(format!("fund value changes from {}", changelog::difference(x.fund_value, fund_value)));
                                     }
                                     if x.unit_value != unit_value {
-                                        println!("Warning bxZohaYm: Fund {} changing unit_value from {} to {}", fund_name, x.unit_value, unit_value);
+                                        differences.push(format!("unit value changes from {}", changelog::difference(x.unit_value, unit_value)));
+                                    }
+                                    let warning = format!("Warning: Fund {} on {}: {} in profit.txt line {}", fund_name, date, differences.join(" and "), line_index + 1);
+                                    if options.overwrite_policy.overwrite(&warning)? {
+                                        changes.push(changelog::Change {
+                                            time: now,
+                                            fund: fund_name.clone(),
+                                            record: "fund-value".to_string(),
+                                            date: Some(date),
+                                            old: vec![format_cents(x.fund_value), format_cents(x.unit_value)],
+                                            new: vec![format_cents(fund_value), format_cents(unit_value)],
+                                            source: format!("profit.txt line {}", line_index + 1),
+                                            reason: "profit.txt has a different fund value".to_string(),
+                                        });
+                                        x.fund_value = fund_value;
                                         x.unit_value = unit_value;
+                                        x.provenance = provenance;
                                     }
                                 }
+                                Some(_) => {}
                                 None => {
                                     series.fund_value.push(FundValue {
                                         date,
                                         fund_value,
                                         unit_value,
+                                        provenance,
                                     })
                                 },
                             }
@@ -674,7 +1250,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                     date,
                                     fund_value,
                                     unit_value,
+                                    provenance,
                                 }],
+                                currency: Currency::Cop,
+                                fee: Vec::new(),
+                                closed: None,
                             });
                         }
                     }
@@ -683,14 +1263,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         roe_next_to_last_year,
                         roe_last_year,
                         roe_year_to_date,
-                        roe_day: 0.,
-                        roe_day_annualized: 0.,
-                        roe_month: 0.,
-                        roe_trimester: 0.,
-                        roe_semester: 0.,
-                        roe_year: 0.,
-                        roe_2_years: 0.,
-                        roe_total: 0.,
+                        roe_day: Percent::NotAvailable,
+                        roe_day_annualized: Percent::NotAvailable,
+                        roe_month: Percent::NotAvailable,
+                        roe_trimester: Percent::NotAvailable,
+                        roe_semester: Percent::NotAvailable,
+                        roe_year: Percent::NotAvailable,
+                        roe_2_years: Percent::NotAvailable,
+                        roe_total: Percent::NotAvailable,
                     });
                 }
                 Mode1::Intermission => {
@@ -704,15 +1284,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     }
                     let mut fields = input.split('\t');
                     let fund_name = parse_name(fields.next(), || format!("Parsing fund name at profit.txt line {} field 1: ", line_index + 1))?;
-                    let roe_day = parse_percent(fields.next(), || format!("Parsing {} returns from last year at profit.txt line {} field 2: ", fund_name, line_index + 1))?;
-                    let roe_day_annualized = parse_percent(fields.next(), || format!("Parsing {} returns from last year at profit.txt line {} field 3: ", fund_name, line_index + 1))?;
-                    let roe_month = parse_percent(fields.next(), || format!("Parsing {} returns from last year at profit.txt line {} field 4: ", fund_name, line_index + 1))?;
-                    let roe_trimester = parse_percent(fields.next(), || format!("Parsing {} returns from last year at profit.txt line {} field 5: ", fund_name, line_index + 1))?;
-                    let roe_semester = parse_percent(fields.next(), || format!("Parsing {} returns from last year at profit.txt line {} field 6: ", fund_name, line_index + 1))?;
-                    let roe_year = parse_percent(fields.next(), || format!("Parsing {} returns from last year at profit.txt line {} field 7: ", fund_name, line_index + 1))?;
-                    let roe_2_years = parse_percent(fields.next(), || format!("Parsing {} returns from last year at profit.txt line {} field 8: ", fund_name, line_index + 1))?;
-                    let roe_total = parse_percent(fields.next(), || format!("Parsing {} returns from last year at profit.txt line {} field 9: ", fund_name, line_index + 1))?;
-                    let _roe_year_to_date = parse_percent(fields.next(), || format!("Parsing {} returns from last year at profit.txt line {} field 10: ", fund_name, line_index + 1))?;
+                    let roe_day = parse_percent_locale(fields.next(), &options.locale, || format!("Parsing {} returns from last year at profit.txt line {} field 2: ", fund_name, line_index + 1))?;
+                    let roe_day_annualized = parse_percent_locale(fields.next(), &options.locale, || format!("Parsing {} returns from last year at profit.txt line {} field 3: ", fund_name, line_index + 1))?;
+                    let roe_month = parse_percent_locale(fields.next(), &options.locale, || format!("Parsing {} returns from last year at profit.txt line {} field 4: ", fund_name, line_index + 1))?;
+                    let roe_trimester = parse_percent_locale(fields.next(), &options.locale, || format!("Parsing {} returns from last year at profit.txt line {} field 5: ", fund_name, line_index + 1))?;
+                    let roe_semester = parse_percent_locale(fields.next(), &options.locale, || format!("Parsing {} returns from last year at profit.txt line {} field 6: ", fund_name, line_index + 1))?;
+                    let roe_year = parse_percent_locale(fields.next(), &options.locale, || format!("Parsing {} returns from last year at profit.txt line {} field 7: ", fund_name, line_index + 1))?;
+                    let roe_2_years = parse_percent_locale(fields.next(), &options.locale, || format!("Parsing {} returns from last year at profit.txt line {} field 8: ", fund_name, line_index + 1))?;
+                    let roe_total = parse_percent_locale(fields.next(), &options.locale, || format!("Parsing {} returns from last year at profit.txt line {} field 9: ", fund_name, line_index + 1))?;
+                    let _roe_year_to_date = parse_percent_locale(fields.next(), &options.locale, || format!("Parsing {} returns from last year at profit.txt line {} field 10: ", fund_name, line_index + 1))?;
                     assert_eq!(fields.count(), 0); // 0 remaining fields
                     match table_aggregate.iter_mut().find(|u| u.fund == fund_name) {
                         Some(x) => {
@@ -740,60 +1320,34 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         });
     }
     // Check fund transfer consistency: Check for that every withdrawal from a fund has a corresponding deposit into another.
+    // Only new withdrawals and deposits are reported; fondos check --all reports the full history again.
     {
-        let non_empty = |s: &&Series| !s.balance.is_empty() && s.balance.last().unwrap().balance != 0;
-        let recent = |a: &&Action| a.date > chrono::NaiveDate::from_ymd(2021, 11, 13);
-        let fund_selection: Vec<_> = table.table.iter().filter(non_empty)
-        .map(|s| (s.fund.clone(), s.balance.last().unwrap())).collect();
-        for s1 in table.table.iter().filter(non_empty) {
-            for a1 in s1.action.iter().filter(recent) {
-                let mut expected_action = a1.clone();
-                expected_action.change = -expected_action.change;
-                let mut match_found = false;
-                for s2 in table.table.iter() {
-                    for a2 in s2.action.iter() {
-                        if *a2 == expected_action {
-                            match_found = true;
-                        }
-                    }
-                }
-                if !match_found {
-                    let best_matching_fund = fund_selection.iter().map(|(n, b)| (n, b, b.balance - a1.change))
-                    .min_by(|a, b| a.2.abs().cmp(&b.2.abs())).unwrap();
-                    println!("{}: no match: {:?}; nearest fund {:?}", s1.fund, a1, best_matching_fund);
-                }
-            }
+        let unmatched = transfer::check(&mut table, false);
+        for u in unmatched.iter() {
+            println!("{}", u);
+        }
+        if !unmatched.is_empty() {
+            println!("These withdrawals and deposits without a pair will not be reported again; run fondos check --all to see them.");
         }
     }
     // println!("Data is not saved to disk. Data {}", if calculate_hash(&table) == original_hash { "remains unchanged." } else { "has changed." }); return Ok(());
     // Save the table to funds.dat
+    provenance::compact(&mut table);
     if calculate_hash(&table) == original_hash {
         println!("Data remains the same. Files remain unchanged.");
     } else {
         println!("Creating new funds file...");
-        let new_file_name = "data/funds.new";
-        let new_path = std::path::Path::new(&new_file_name);
-        {
-            let new_file = fs::File::create(new_path).or_else(|e| Err(format!("Error writing to temporary file {}: {}", new_file_name, e)))?;
-            bincode::serialize_into(new_file, &table).or_else(|e| Err(format!("Error writing to temporary file {}: {}", new_file_name, e)))?;
-        }
-        if db_path.exists() {
-            let backup_file_name = format!(
-                "data/funds_backup{}.dat",
-                chrono::Local::now().format("%Y%m%dT%H%M%S")
-            );
-            let to = std::path::Path::new(&backup_file_name);
-            fs::rename(db_path, to).or_else(|e| Err(format!("Error creating backup {}: {}", backup_file_name, e)))?;
-        }
-        fs::rename(new_path, db_path).or_else(w_err0)?;
+        storage::save(&table, funds_file_name)?;
+        changelog::append(change_log_file_name, &changes)?;
     }
     {
-        // Delete any png and csv files from previous runs.
+        // Delete any png and csv files, and any svg and pdf figures, from previous runs.
         for res in std::fs::read_dir(".")? {
             if let Ok(entry) = res {
                 let path = entry.path();
                 if let Some(extension) = path.extension() {
-                    if (extension == "png") || (extension == "csv") {
+                    let is_figure = path.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with("fondos"));
+                    if (extension == "png") || (extension == "csv") || (is_figure && ((extension == "svg") || (extension == "pdf"))) {
                         if let Some(file_name_os_str) = path.file_name() {
                             if let Some(file_name) = file_name_os_str.to_str() {
                                 if let Err(e) = fs::remove_file(&path) {
@@ -815,7 +1369,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let csv_err = |e| Err(format!("Error writing to {}: {}", csv_file_name, e));
         let csv_file = create_file(csv_file_name)?;
         writeln!(&csv_file, "Portafolio,Dia %,Dia %EA,Mes %,3 Meses,6 Meses,Ano corrido,Ano,Ano pasado,Hace 2 anos,Ultimos 2 anos,Desde el inicio").or_else(csv_err)?;
-        for f in table_aggregate {
+        for f in table_aggregate.iter() {
             writeln!(&csv_file, "{},{},{},{},{},{},{},{},{},{},{},{}", f.fund, f.roe_day, f.roe_day_annualized, f.roe_month, f.roe_trimester, f.roe_semester, f.roe_year_to_date, f.roe_year, f.roe_last_year, f.roe_next_to_last_year, f.roe_2_years, f.roe_total).or_else(csv_err)?;
         }
     }
@@ -824,22 +1378,142 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let csv_file_name = "comparison.csv";
         let csv_file = create_file(csv_file_name)?;
         let csv_err = |e| Err(format!("Error writing to {}: {}", csv_file_name, e));
-        writeln!(&csv_file, "Fund,Previous date,Previous $,Change,Last date,Last $").or_else(csv_err)?;
-        for series in table.table.iter() {
-            let mut it = series.balance.iter().rev();
-            if let Some(last_record) = it.next() {
-                write!(&csv_file, "{}", &series.fund).or_else(csv_err)?;
-                let last_record_balance = last_record.balance as f64 / 100.0;
-                if let Some(next_to_last_record) = it.next() {
-                    let next_to_last_record_balance = next_to_last_record.balance as f64 / 100.0;
-                    write!(&csv_file, ",{},{},{}", next_to_last_record.date, next_to_last_record_balance, last_record_balance - next_to_last_record_balance).or_else(csv_err)?;
-                } else {
-                    write!(&csv_file, ",,,").or_else(csv_err)?;
+        write!(&csv_file, "{}", comparison_csv(&table)).or_else(csv_err)?;
+    }
+    // Save risk metrics calculated from the full history of unit values to file risk.csv
+    {
+        let csv_file_name = "risk.csv";
+        let csv_file = create_file(csv_file_name)?;
+        let csv_err = |e| Err(format!("Error writing to {}: {}", csv_file_name, e));
+        writeln!(&csv_file, "Fund,First date,Last date,Observations,Annual return %,Volatility %,Downside deviation %,Max drawdown %,Peak date,Trough date,Recovery date,Recovery days,Sharpe,Sortino,Risk-free rate %").or_else(csv_err)?;
+        for series in table.open_series() {
+            if let Some(r) = risk::risk_metrics(&series.fund_value, options.risk_free_rate) {
+                let d = &r.max_drawdown;
+                let recovery_date = d.recovery_date.map(|d| d.to_string()).unwrap_or_default();
+                let recovery_days = d.recovery_days().map(|d| d.to_string()).unwrap_or_default();
+                writeln!(&csv_file, "{},{},{},{},{:.4},{:.4},{:.4},{:.4},{},{},{},{},{},{},{}", series.fund, r.start_date, r.end_date, r.observations, r.annual_return, r.volatility, r.downside_deviation, d.percent, d.peak_date, d.trough_date, recovery_date, recovery_days, risk::format_ratio(r.sharpe, 4), risk::format_ratio(r.sortino, 4), options.risk_free_rate).or_else(csv_err)?;
+            }
+        }
+    }
+    // Save correlations between the daily returns of funds to file correlation.csv
+    let correlation_funds: Vec<String> = table.table.iter().filter(|s| s.fund_value.len() > 2).map(|s| s.fund.clone()).collect();
+    let correlation_matrices: Vec<_> = {
+        let returns_vec: Vec<_> = table.table.iter().filter(|s| s.fund_value.len() > 2).map(|s| correlation::returns(&s.fund_value)).collect();
+        options.correlation_windows.iter().map(|window| correlation::correlation_matrix(&returns_vec, date, *window)).collect()
+    };
+    {
+        let csv_file_name = "correlation.csv";
+        let csv_file = create_file(csv_file_name)?;
+        let csv_err = |e| Err(format!("Error writing to {}: {}", csv_file_name, e));
+        writeln!(&csv_file, "Window days,Fund,{}", correlation_funds.join(",")).or_else(csv_err)?;
+        for m in correlation_matrices.iter() {
+            for (fund, row) in correlation_funds.iter().zip(m.matrix.iter()) {
+                let row_str: Vec<_> = row.iter().map(|c| c.map(|c| format!("{:.4}", c)).unwrap_or_else(|| "NA".to_string())).collect();
+                writeln!(&csv_file, "{},{},{}", m.window, fund, row_str.join(",")).or_else(csv_err)?;
+            }
+        }
+    }
+    // Save real balances and returns, discounting inflation, to file real.csv
+    match &cpi {
+        Some(cpi) => {
+            let csv_file_name = "real.csv";
+            let csv_file = create_file(csv_file_name)?;
+            let csv_err = |e| Err(format!("Error writing to {}: {}", csv_file_name, e));
+            writeln!(&csv_file, "Fund,First date,Last date,Nominal return %,Inflation %,Real return %,Last $,Last $ in pesos of first date").or_else(csv_err)?;
+            for series in table.table.iter() {
+                if let (Some(first), Some(last)) = (series.fund_value.first(), series.fund_value.last()) {
+                    if first.unit_value > 0 && first.date < last.date {
+                        let nominal_return = 100.0 * last.unit_value as f64 / first.unit_value as f64 - 100.0;
+                        let inflation = cpi.inflation(first.date, last.date);
+                        write!(&csv_file, "{},{},{},{:.4},{:.4},{:.4}", series.fund, first.date, last.date, nominal_return, inflation, inflation::real_return(nominal_return, inflation)).or_else(csv_err)?;
+                        match series.balance.last() {
+                            Some(b) => writeln!(&csv_file, ",{},{}", b.balance as f64 / 100.0, cpi.deflate(b.balance, b.date, first.date) as f64 / 100.0).or_else(csv_err)?,
+                            None => writeln!(&csv_file, ",,").or_else(csv_err)?,
+                        }
+                    }
                 }
-                writeln!(&csv_file, ",{},{}", last_record.date, last_record_balance).or_else(csv_err)?;
             }
-        };
+            if let Some(first_date) = table.table.iter().filter_map(|s| s.balance.first()).map(|b| b.date).min() {
+                let portfolio = risk::portfolio_index(&table.table, first_date);
+                let last = portfolio.last().unwrap();
+                if last.0 > first_date {
+                    let nominal_return = last.1 - 100.0;
+                    let inflation = cpi.inflation(first_date, last.0);
+                    let last_balance: Cents = table.table.iter().filter_map(|s| s.balance.last()).map(|b| b.balance).sum();
+                    writeln!(&csv_file, "portafolio,{},{},{:.4},{:.4},{:.4},{},{}", first_date, last.0, nominal_return, inflation, inflation::real_return(nominal_return, inflation), last_balance as f64 / 100.0, cpi.deflate(last_balance, last.0, first_date) as f64 / 100.0).or_else(csv_err)?;
+                }
+            }
+        }
+        None => println!("File {} not found; real.csv is not created.", cpi_file_name),
+    }
+    // Save the comparison of each fund against its benchmark to file benchmark.csv
+    if !benchmark_assignments.is_empty() {
+        let csv_file_name = "benchmark.csv";
+        let csv_file = create_file(csv_file_name)?;
+        let csv_err = |e| Err(format!("Error writing to {}: {}", csv_file_name, e));
+        writeln!(&csv_file, "Fund,Benchmark,First date,Last date,Observations,Excess return %,Tracking error %,Beta,Information ratio").or_else(csv_err)?;
+        for (fund, benchmark_name) in benchmark_assignments.iter() {
+            let fund_value = table.table.iter().find(|s| s.fund == *fund).map(|s| s.fund_value.as_slice()).unwrap_or(&[]);
+            let benchmark_value = &benchmarks.iter().find(|b| b.fund == *benchmark_name).unwrap().fund_value;
+            writeln!(&csv_file, "{}", benchmark::csv_record(fund, benchmark_name, benchmark::compare(fund_value, benchmark_value).as_ref())).or_else(csv_err)?;
+        }
     }
+    // Save yearly costs of the funds to file fees.csv
+    {
+        let costs: Vec<_> = table.table.iter().flat_map(|s| fees::yearly_costs(s, date)).collect();
+        if costs.is_empty() {
+            println!("No fund has fees or fee charges; fees.csv is not created.");
+        } else {
+            let csv_file_name = "fees.csv";
+            let csv_file = create_file(csv_file_name)?;
+            let csv_err = |e| Err(format!("Error writing to {}: {}", csv_file_name, e));
+            writeln!(&csv_file, "Fund,Year,First date,Last date,Average $,Annual fee % at last date,Estimated fees,Fee charges,Total cost,Total cost annual %").or_else(csv_err)?;
+            for c in costs {
+                use chrono::Datelike;
+                let series = table.table.iter().find(|s| s.fund == c.fund).unwrap();
+                writeln!(&csv_file, "{},{},{},{},{},{},{},{},{},{:.4}", c.fund, c.start_date.year(), c.start_date, c.end_date, c.average_balance as f64 / 100.0, fees::annual_fee_on(series, c.end_date) as f64 / 100.0, c.estimated_fees as f64 / 100.0, c.fee_charges as f64 / 100.0, c.total() as f64 / 100.0, c.annual_percent()).or_else(csv_err)?;
+            }
+        }
+    }
+    // Save returns and balances in pesos and dollars to file currency.csv
+    match &exchange_rate {
+        Some(rate) => {
+            let csv_file_name = "currency.csv";
+            let csv_file = create_file(csv_file_name)?;
+            let csv_err = |e| Err(format!("Error writing to {}: {}", csv_file_name, e));
+            writeln!(&csv_file, "Fund,Currency,First date,Last date,Return COP %,Return USD %,Exchange rate change %,Last date of balance,Last $ COP,Last $ USD").or_else(csv_err)?;
+            for series in table.table.iter() {
+                write!(&csv_file, "{},{}", series.fund, series.currency).or_else(csv_err)?;
+                match (series.fund_value.first(), series.fund_value.last()) {
+                    (Some(first), Some(last)) if first.unit_value > 0 && first.date < last.date => {
+                        let r = currency::currency_return(rate, first.date, first.unit_value, last.date, last.unit_value);
+                        write!(&csv_file, ",{},{},{:.4},{:.4},{:.4}", first.date, last.date, r.return_cop, r.return_usd, r.exchange_rate_change).or_else(csv_err)?;
+                    }
+                    _ => write!(&csv_file, ",,,,,").or_else(csv_err)?,
+                }
+                match series.balance.last() {
+                    Some(b) => writeln!(&csv_file, ",{},{},{}", b.date, b.balance as f64 / 100.0, rate.to_usd(b.balance, b.date) as f64 / 100.0).or_else(csv_err)?,
+                    None => writeln!(&csv_file, ",,,").or_else(csv_err)?,
+                }
+            }
+            for c in &[Currency::Cop, Currency::Usd] {
+                let (total_cop, total_usd) = table
+                    .table
+                    .iter()
+                    .filter(|s| s.currency == *c)
+                    .filter_map(|s| s.balance.last())
+                    .fold((0, 0), |(cop, usd), b| (cop + b.balance, usd + rate.to_usd(b.balance, b.date)));
+                writeln!(&csv_file, "total,{},,,,,,,{},{}", c, total_cop as f64 / 100.0, total_usd as f64 / 100.0).or_else(csv_err)?;
+            }
+        }
+        None => println!("File {} not found; currency.csv is not created.", exchange_rate_file_name),
+    }
+    let durations = &[7, 15, 30, 70]; // Days
+    let accessible_funds = vec!["acciones colombia",  "acciones global",  "capital",  "consumo global",  "diver dinamico",  "diver moderado",  "diver. conservador",  "estable", "preserva",  "renta fija global",  "renta fija pesos",  "sostenible global"];
+    // Closed funds are left out of the charts and the report
+    let accessible_funds: Vec<_> = accessible_funds.into_iter().filter(|f| !table.table.iter().any(|s| s.fund == *f && s.closed.is_some())).collect();
+    // Save the report with tables and charts to file report.html
+    report::write_html("report.html", &table, &table_aggregate, &accessible_funds, durations, date, options.risk_free_rate)?;
     {
         let background_color = &BLACK;
         let _background_fill = background_color.filled();
@@ -900,9 +1574,40 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let text2 = ("Calibri", text_size2).into_font().color(color0);
         use plotters::style::text_anchor::{HPos, Pos, VPos};
         let _text2c = text2.pos(Pos::new(HPos::Center, VPos::Top));
-        let durations = &[7, 15, 30, 70]; // Days
-        // Retain recent records for plotting
+        // Draws a figure with the backend of the format chosen with option --format.
+        macro_rules! draw_figure {
+            ($figure_format:expr, $figure_name:expr, |$drawing_area0:ident| $body:block) => {{
+                let figure_file_name = format!("{}.{}", $figure_name, $figure_format.extension());
+                let figure_path = std::path::Path::new(&figure_file_name);
+                if figure_path.exists() {
+                    panic!(
+                        "This program just tried to rewrite {}; please debug",
+                        figure_path.to_str().unwrap()
+                    );
+                }
+                match $figure_format {
+                    figure::FigureFormat::Png => {
+                        let $drawing_area0 = BitMapBackend::new(figure_path, (1920, 1080)).into_drawing_area();
+                        $body
+                    }
+                    figure::FigureFormat::Svg => {
+                        let $drawing_area0 = SVGBackend::new(figure_path, (1920, 1080)).into_drawing_area();
+                        $body
+                    }
+                    figure::FigureFormat::Pdf => {
+                        let mut svg = String::new();
+                        {
+                            let $drawing_area0 = SVGBackend::with_string(&mut svg, (1920, 1080)).into_drawing_area();
+                            $body
+                        }
+                        fs::write(figure_path, figure::pdf_from_svg(&svg)?).map_err(|e| format!("Error writing to {}: {}", figure_file_name, e))?;
+                    }
+                }
+            }};
+        }
+        // Retain open funds and recent records for plotting
         {
+            table.table.retain(|s| s.closed.is_none());
             let max_duration = durations.iter().max().unwrap();
             let minimum_date = date
                 .checked_sub_signed(chrono::Duration::days(*max_duration))
@@ -913,16 +1618,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 series.fund_value.retain(|r| r.date >= minimum_date);
             });
         }
-        {
-            let figure_file_name = "fondos00.png";
-            let figure_path = std::path::Path::new(&figure_file_name);
-            if figure_path.exists() {
-                panic!(
-                    "This program just tried to rewrite {}; please debug",
-                    figure_path.to_str().unwrap()
-                );
-            }
-            let drawing_area0 = BitMapBackend::new(figure_path, (1920, 1080)).into_drawing_area();
+        draw_figure!(options.figure_format, "fondos00", |drawing_area0| {
             drawing_area0.fill(background_color).unwrap();
             drawing_area0
                 .split_evenly((2, columns(durations.len())))
@@ -935,6 +1631,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                             let today_date = Date::from_utc(date, chrono::Utc);
                             let ranged_date =
                                 plotters::coord::types::RangedDate::from(start_date..today_date);
+                            // With option --real, express amounts in pesos of start_naive_date
+                            let deflate = |cents: Cents, date: chrono::NaiveDate| match &cpi {
+                                Some(cpi) if options.real => cpi.deflate(cents, date, start_naive_date),
+                                _ => cents,
+                            };
                             // Calculate consolidated balances across funds
                             let (consolidated_balance_i, consolidated_investment_i) = table
                                 .table
@@ -943,14 +1644,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                     match series.balance.iter().find(|b| b.date >= start_naive_date)
                                     {
                                         Some(initial_balance) => (
-                                            accum_balance + series.balance.last().unwrap().balance,
+                                            accum_balance + {
+                                                let last_balance = series.balance.last().unwrap();
+                                                deflate(last_balance.balance, last_balance.date)
+                                            },
                                             accum_investment
-                                                + initial_balance.balance
+                                                + deflate(initial_balance.balance, initial_balance.date)
                                                 + series
                                                     .action
                                                     .iter()
                                                     .skip_while(|a| a.date < initial_balance.date)
-                                                    .map(|a| a.change)
+                                                    .filter(|a| a.is_flow())
+                                                    .map(|a| deflate(a.change, a.date))
                                                     .sum::<i64>(),
                                         ),
                                         None => (accum_balance, accum_investment),
@@ -980,10 +1685,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                             .action
                                             .iter()
                                             .skip_while(|a| a.date < initial_balance.date) // skip_while() creates a new iter.
+                                            .filter(|a| a.is_flow())
                                             .peekable();
                                         balance_iter
-                                            .scan(initial_balance.balance, |running_balance, b| {
-                                                let mut adjusted_current_balance = b.balance;
+                                            .scan(deflate(initial_balance.balance, initial_balance.date), |running_balance, b| {
+                                                let current_balance = deflate(b.balance, b.date);
+                                                let mut adjusted_current_balance = current_balance;
                                                 let unadjusted_running_balance = *running_balance;
                                                 #[allow(clippy::while_let_on_iterator)]
                                                 while let Some(action) = action_iter.peek() {
@@ -991,12 +1698,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                                     if action.date >= b.date {
                                                         break;
                                                     }
-                                                    *running_balance += action.change;
-                                                    adjusted_current_balance -= action.change;
+                                                    let change = deflate(action.change, action.date);
+                                                    *running_balance += change;
+                                                    adjusted_current_balance -= change;
                                                     action_iter.next();
                                                 }
                                                 let variation1 = adjusted_current_balance - unadjusted_running_balance;
-                                                let variation2 = b.balance - *running_balance;
+                                                let variation2 = current_balance - *running_balance;
                                                 Some((
                                                     Date::from_utc(b.date, chrono::Utc),
                                                     if variation1.abs() > variation2.abs() {
@@ -1048,8 +1756,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                 .margin(figure_margin)
                                 .caption(
                                     format!(
-                                        "{} días (inversión ${:.2}, rendimiento ${:.2} ({:.2}%))",
+                                        "{} días{} (inversión ${:.2}, rendimiento ${:.2} ({:.2}%))",
                                         duration,
+                                        if options.real { " en pesos constantes" } else { "" },
                                         consolidated_investment,
                                         consolidated_variation,
                                         consolidated_variation_percent,
@@ -1078,7 +1787,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                 chart
                                     .draw_series(LineSeries::new(
                                         series.variation.clone(),
-                                        color_vec[index].stroke_width(thick_stroke),
+                                        color_vec[index % color_vec.len()].stroke_width(thick_stroke),
                                     ))
                                     .unwrap();
                             }
@@ -1122,7 +1831,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                             &format!("{} {:.2}", label.fund, label.variation),
                                             &("Calibri", text_size1)
                                                 .into_font()
-                                                .color(color_vec[label.index]),
+                                                .color(color_vec[label.index % color_vec.len()]),
                                             coord,
                                         )
                                         .unwrap();
@@ -1135,19 +1844,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         ),
                     }
                 });
-        }
+        });
         // Unit value as a proportion of the initial value
-        {
-            let accessible_funds = vec!["acciones colombia",  "acciones global",  "capital",  "consumo global",  "diver dinamico",  "diver moderado",  "diver. conservador",  "estable", "preserva",  "renta fija global",  "renta fija pesos",  "sostenible global"];
-            let figure_file_name = "fondos01.png";
-            let figure_path = std::path::Path::new(&figure_file_name);
-            if figure_path.exists() {
-                panic!(
-                    "This program just tried to rewrite {}; please debug",
-                    figure_path.to_str().unwrap()
-                );
-            }
-            let drawing_area0 = BitMapBackend::new(figure_path, (1920, 1080)).into_drawing_area();
+        draw_figure!(options.figure_format, "fondos01", |drawing_area0| {
             drawing_area0.fill(background_color).unwrap();
             drawing_area0
                 .split_evenly((2, columns(durations.len())))
@@ -1160,13 +1859,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                             let today_date = Date::from_utc(date, chrono::Utc);
                             let ranged_date =
                                 plotters::coord::types::RangedDate::from(start_date..today_date);
+                            let in_range = |series: &&Series| series.fund_value.iter().any(|b| b.date >= start_naive_date);
+                            let fund_filter = |series: &&Series| in_range(series) && accessible_funds.contains(&series.fund.as_str());
+                            // Benchmarks follow the funds in series_vec
+                            let n_funds = table.table.iter().filter(fund_filter).count();
                             let series_vec: Vec<_> = table
                                 .table
                                 .iter()
-                                .filter(|series: &&Series| {
-                                    series.fund_value.iter().any(|b| b.date >= start_naive_date)
-                                        && accessible_funds.contains(&series.fund.as_str())
-                                })
+                                .filter(fund_filter)
+                                .chain(benchmarks.iter().filter(in_range))
                                 .map(|series: &Series| PlotSeries {
                                     fund: series.fund.clone(),
                                     variation: {
@@ -1242,11 +1943,174 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                 .label_style(text2.clone())
                                 .draw()
                                 .unwrap();
+                            for (index, series) in series_vec.iter().enumerate() {
+                                if index < n_funds {
+                                    chart
+                                        .draw_series(LineSeries::new(
+                                            series.variation.clone(),
+                                            color_vec[index % color_vec.len()].stroke_width(thick_stroke),
+                                        ))
+                                        .unwrap();
+                                } else {
+                                    chart
+                                        .draw_series(DashedLineSeries::new(
+                                            series.variation.clone(),
+                                            12,
+                                            8,
+                                            color_vec[index % color_vec.len()].stroke_width(thick_stroke),
+                                        ))
+                                        .unwrap();
+                                }
+                            }
+                            let mut labels: Vec<_> = series_vec
+                                .iter()
+                                .enumerate()
+                                .map(|(index, series)| Label {
+                                    index,
+                                    fund: &series.fund,
+                                    variation: series.variation.last().unwrap().1,
+                                    backend_coord: {
+                                        let mut bc = chart.backend_coord(&(
+                                            start_date,
+                                            series.variation.last().unwrap().1,
+                                        ));
+                                        bc.0 += 20;
+                                        bc
+                                    },
+                                })
+                                .collect();
+                            labels.sort_unstable_by(|p1, p2| {
+                                p1.backend_coord.1.cmp(&p2.backend_coord.1)
+                            });
+                            let backend_y_range = (
+                                chart.backend_coord(&(start_date, max_variation)).1,
+                                chart.backend_coord(&(start_date, min_variation)).1
+                                    - line_spacing * labels.len() as i32,
+                            );
+                            labels
+                                .iter()
+                                .fold(backend_y_range, |(min_y, max_y), label| {
+                                    let mut coord = label.backend_coord;
+                                    if coord.1 < min_y {
+                                        coord.1 = min_y;
+                                    }
+                                    if coord.1 > max_y {
+                                        coord.1 = max_y;
+                                    }
+                                    drawing_area0
+                                        .draw_text(
+                                            &format!("{} {:.2}%", label.fund, label.variation),
+                                            &("Calibri", text_size1)
+                                                .into_font()
+                                                .color(color_vec[label.index % color_vec.len()]),
+                                            coord,
+                                        )
+                                        .unwrap();
+                                    (coord.1 + line_spacing, max_y + line_spacing)
+                                });
+                        }
+                        None => eprintln!(
+                            "Error subtracting duration {} from date {}. Please review the code.",
+                            *duration, date
+                        ),
+                    }
+                });
+        });
+        // Drawdown: how far the unit value of each fund, and the consolidated portfolio, is below its peak
+        draw_figure!(options.figure_format, "fondos02", |drawing_area0| {
+            drawing_area0.fill(background_color).unwrap();
+            drawing_area0
+                .split_evenly((2, columns(durations.len())))
+                .iter()
+                .zip(durations.iter().enumerate())
+                .for_each(|(drawing_area1, (duration_index, duration))| {
+                    match date.checked_sub_signed(chrono::Duration::days(*duration)) {
+                        Some(start_naive_date) => {
+                            let start_date = Date::from_utc(start_naive_date, chrono::Utc);
+                            let today_date = Date::from_utc(date, chrono::Utc);
+                            let ranged_date =
+                                plotters::coord::types::RangedDate::from(start_date..today_date);
+                            let to_plot = |values: Vec<(chrono::NaiveDate, f64)>| -> Vec<Variation> {
+                                risk::underwater(&values)
+                                    .into_iter()
+                                    .map(|(d, v)| (Date::from_utc(d, chrono::Utc), v))
+                                    .collect()
+                            };
+                            let portfolio = risk::portfolio_index(&table.table, start_naive_date);
+                            let series_vec: Vec<_> = std::iter::once(PlotSeries {
+                                fund: "portafolio".to_string(),
+                                variation: to_plot(portfolio),
+                            })
+                            .chain(
+                                table
+                                    .table
+                                    .iter()
+                                    .filter(|series: &&Series| {
+                                        series.fund_value.iter().any(|b| b.date >= start_naive_date)
+                                            && accessible_funds.contains(&series.fund.as_str())
+                                    })
+                                    .map(|series: &Series| PlotSeries {
+                                        fund: series.fund.clone(),
+                                        variation: to_plot(
+                                            series
+                                                .fund_value
+                                                .iter()
+                                                .skip_while(|b| b.date < start_naive_date)
+                                                .map(|b| (b.date, b.unit_value as f64))
+                                                .collect(),
+                                        ),
+                                    }),
+                            )
+                            .filter(|series| !series.variation.is_empty())
+                            .collect();
+                            let min_variation = series_vec
+                                .iter()
+                                .flat_map(|series| series.variation.iter().map(|a| a.1))
+                                .min_by(|a, b| a.partial_cmp(b).unwrap())
+                                .unwrap_or(-1.0);
+                            let max_variation = 0.;
+                            let variation_expansion = {
+                                let variation_expansion = 0.02 * (max_variation - min_variation);
+                                if variation_expansion > 0. {
+                                    variation_expansion
+                                } else {
+                                    1.
+                                }
+                            };
+                            let variation_range = (min_variation - variation_expansion)
+                                ..(max_variation + variation_expansion);
+                            let mut chart = ChartBuilder::on(drawing_area1)
+                                .x_label_area_size(x_label_area_size)
+                                .y_label_area_size(if duration_index == 0 {
+                                    y_label_area_size0
+                                } else {
+                                    y_label_area_size1
+                                })
+                                .margin(figure_margin)
+                                .caption(format!("Caída desde el máximo {} días", duration,), text0.clone())
+                                .build_cartesian_2d(ranged_date, variation_range)
+                                .unwrap();
+                            chart
+                                .configure_mesh()
+                                .bold_line_style(color02)
+                                .light_line_style(color01)
+                                .x_desc("Fecha")
+                                .y_desc(if duration_index == 0 {
+                                    "Caída desde el máximo (%)"
+                                } else {
+                                    ""
+                                })
+                                .x_label_formatter(&date_formatter)
+                                .axis_style(color0)
+                                .axis_desc_style(text2.clone())
+                                .label_style(text2.clone())
+                                .draw()
+                                .unwrap();
                             for (index, series) in series_vec.iter().enumerate() {
                                 chart
                                     .draw_series(LineSeries::new(
                                         series.variation.clone(),
-                                        color_vec[index].stroke_width(thick_stroke),
+                                        color_vec[index % color_vec.len()].stroke_width(thick_stroke),
                                     ))
                                     .unwrap();
                             }
@@ -1290,7 +2154,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                             &format!("{} {:.2}%", label.fund, label.variation),
                                             &("Calibri", text_size1)
                                                 .into_font()
-                                                .color(color_vec[label.index]),
+                                                .color(color_vec[label.index % color_vec.len()]),
                                             coord,
                                         )
                                         .unwrap();
@@ -1303,14 +2167,152 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         ),
                     }
                 });
-        }
+        });
+        // Correlations between funds as a heatmap
+        draw_figure!(options.figure_format, "fondos03", |drawing_area0| {
+            drawing_area0.fill(background_color).unwrap();
+            let n_funds = correlation_funds.len() as i32;
+            drawing_area0
+                .split_evenly((2, columns(correlation_matrices.len())))
+                .iter()
+                .zip(correlation_matrices.iter().enumerate())
+                .for_each(|(drawing_area1, (window_index, m))| {
+                    let mut chart = ChartBuilder::on(drawing_area1)
+                        .x_label_area_size(x_label_area_size)
+                        .y_label_area_size(if window_index % columns(correlation_matrices.len()) == 0 {
+                            y_label_area_size0 + 150
+                        } else {
+                            y_label_area_size1 + 150
+                        })
+                        .margin(figure_margin)
+                        .caption(format!("Correlación {} días", m.window), text0.clone())
+                        .build_cartesian_2d((0..(n_funds - 1).max(0)).into_segmented(), (0..(n_funds - 1).max(0)).into_segmented())
+                        .unwrap();
+                    let fund_label = |v: &SegmentValue<i32>, with_name: bool| match v {
+                        SegmentValue::CenterOf(i) if *i < n_funds => {
+                            if with_name {
+                                format!("{} {}", i + 1, correlation_funds[*i as usize])
+                            } else {
+                                format!("{}", i + 1)
+                            }
+                        }
+                        _ => String::new(),
+                    };
+                    chart
+                        .configure_mesh()
+                        .disable_mesh()
+                        .x_desc("Fondo")
+                        .x_labels(correlation_funds.len())
+                        .y_labels(correlation_funds.len())
+                        .x_label_formatter(&|v| fund_label(v, false))
+                        .y_label_formatter(&|v| fund_label(v, true))
+                        .axis_style(color0)
+                        .axis_desc_style(text2.clone())
+                        .label_style(text2.clone())
+                        .draw()
+                        .unwrap();
+                    for (i, row) in m.matrix.iter().enumerate() {
+                        for (j, c) in row.iter().enumerate() {
+                            let (i, j) = (i as i32, j as i32);
+                            let cell_color = match c {
+                                Some(c) if *c >= 0. => color2.mix(*c),
+                                Some(c) => color6.mix(-*c),
+                                None => color01,
+                            };
+                            chart
+                                .draw_series(std::iter::once(Rectangle::new(
+                                    [(SegmentValue::Exact(j), SegmentValue::Exact(i)), (SegmentValue::Exact(j + 1), SegmentValue::Exact(i + 1))],
+                                    cell_color.filled(),
+                                )))
+                                .unwrap();
+                            if let Some(c) = c {
+                                chart
+                                    .draw_series(std::iter::once(Text::new(
+                                        format!("{:.2}", c),
+                                        (SegmentValue::CenterOf(j), SegmentValue::CenterOf(i)),
+                                        ("Calibri", text_size2).into_font().color(color0).pos(Pos::new(HPos::Center, VPos::Center)),
+                                    )))
+                                    .unwrap();
+                            }
+                        }
+                    }
+                });
+        });
     }
-    println!("Figures and data files are ready. Please run the following:\n    start *.png\n    start *.csv");
+    println!("Figures and data files are ready. Please open report.html in a web browser; the figures are fondos*.{} and the data files are *.csv.", options.figure_format.extension());
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
+    #[test]
+    fn options0() {
+        let options = super::parse_options(vec!["--risk-free-rate".to_string(), "3.25 %".to_string()]).unwrap();
+        assert_eq!(options.risk_free_rate, 3.25);
+    }
+    #[test]
+    fn options1() {
+        assert_eq!(super::parse_options(vec!["--risk-free-rate".to_string()]).err(), Some("Parsing option --risk-free-rate: No valid amount".to_string()));
+    }
+    #[test]
+    fn options3() {
+        let options = super::parse_options(vec!["set-currency".to_string(), "Acciones Global".to_string(), "usd".to_string()]).unwrap();
+        assert_eq!(options.command, super::Command::SetCurrency("acciones global".to_string(), super::Currency::Usd));
+        assert!(super::parse_options(vec!["set-currency".to_string(), "capital".to_string()]).is_err());
+    }
+    #[test]
+    fn options4() {
+        assert_eq!(super::parse_options(vec!["tax-report".to_string(), "2021".to_string()]).unwrap().command, super::Command::TaxReport(2021));
+        assert_eq!(super::parse_options(vec!["tax-report".to_string(), "21x".to_string()]).err(), Some("Parsing year for tax-report: 21x is not a valid year".to_string()));
+    }
+    #[test]
+    fn options5() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let date = chrono::NaiveDate::from_ymd_opt(2022, 1, 1).unwrap();
+        assert_eq!(super::parse_options(args(&["set-fee", "Capital", "01/01/2022", "1.75"])).unwrap().command, super::Command::SetFee("capital".to_string(), date, 175));
+        assert_eq!(super::parse_options(args(&["set-fee", "capital", "01/01/2022", "150.0"])).err(), Some("Parsing annual fee for set-fee: The fee must be a percentage between 0 and 100".to_string()));
+        assert_eq!(super::parse_options(args(&["charge-fee", "capital", "01/01/2022", "$1,500.00"])).unwrap().command, super::Command::ChargeFee("capital".to_string(), date, 150000));
+        assert_eq!(super::parse_options(args(&["fees", "01/01/2022", "31/12/2021"])).err(), Some("Parsing dates for fees: The last date precedes the first date".to_string()));
+    }
+    #[test]
+    fn options6() {
+        assert_eq!(super::parse_options(vec!["serve".to_string()]).unwrap().command, super::Command::Serve(8080));
+        assert_eq!(super::parse_options(vec!["serve".to_string(), "8000".to_string()]).unwrap().command, super::Command::Serve(8000));
+        assert_eq!(super::parse_options(vec!["serve".to_string(), "x".to_string()]).err(), Some("Parsing port for serve: x is not a valid port".to_string()));
+    }
+    #[test]
+    fn options7() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        match super::parse_options(args(&["delete", "balance", "Capital", "20/11/2021"])).unwrap().command {
+            super::Command::Correct(c) => assert_eq!((c.operation, c.fund.as_str(), c.amounts.len()), (super::edit::Operation::Delete, "capital", 0)),
+            command => panic!("{:?}", command),
+        }
+        assert!(super::parse_options(args(&["edit", "fund", "capital", "20/11/2021"])).is_err());
+        assert_eq!(super::parse_options(args(&["check", "--all"])).unwrap().command, super::Command::Check(true));
+        assert!(super::parse_options(args(&["--all"])).is_err());
+        assert_eq!(super::parse_options(args(&["audit", "--max-change", "2.5", "--max-gap", "10"])).unwrap().command, super::Command::Audit(super::quality::Thresholds { max_gap: 10, max_change: 2.5 }));
+        assert!(super::parse_options(args(&["audit", "--max-gap", "-1"])).is_err());
+        assert_eq!(super::parse_options(args(&["reconcile", "Capital", "--tolerance", "$1.00"])).unwrap().command, super::Command::Reconcile(Some("capital".to_string()), 100));
+        assert_eq!(super::parse_options(args(&["reconcile"])).unwrap().command, super::Command::Reconcile(None, 10000));
+        assert_eq!(super::parse_options(args(&["changes", "--fund", "Capital"])).unwrap().command, super::Command::Changes(Some("capital".to_string())));
+        assert_eq!(super::parse_options(args(&["undo", "3"])).unwrap().command, super::Command::Undo(3));
+        assert!(super::parse_options(args(&["undo", "0"])).is_err());
+    }
+    #[test]
+    fn options8() {
+        let options = super::parse_options(vec!["--on-conflict".to_string(), "ask".to_string(), "--real".to_string()]).unwrap();
+        assert_eq!((options.overwrite_policy, options.real), (super::changelog::OverwritePolicy::Ask, true));
+        assert_eq!(super::parse_options(vec!["--on-conflict".to_string(), "never".to_string()]).err(), Some("Parsing option --on-conflict: Policy never not recognized; use overwrite, keep, ask, or fail".to_string()));
+        assert_eq!(super::parse_options(Vec::new()).unwrap().overwrite_policy, super::changelog::OverwritePolicy::Overwrite);
+    }
+    #[test]
+    fn options2() {
+        let options = super::parse_options(vec!["--correlation-windows".to_string(), "7, 60".to_string()]).unwrap();
+        assert_eq!(options.correlation_windows, vec![7, 60]);
+        assert_eq!(super::parse_options(vec!["--correlation-windows".to_string(), "7,0".to_string()]).err(), Some("Parsing option --correlation-windows: 0 is not a number of days between 1 and 36500".to_string()));
+        assert!(super::parse_options(vec!["--correlation-windows".to_string(), "36500".to_string()]).is_ok());
+        assert_eq!(super::parse_options(vec!["--correlation-windows".to_string(), "9223372036854775807".to_string()]).err(), Some("Parsing option --correlation-windows: 9223372036854775807 is not a number of days between 1 and 36500".to_string()));
+    }
     #[test]
     fn cents0() {
         assert_eq!(super::parse_cents(Some("$.00"), || "Test: ".to_string()), Ok(0));
@@ -1352,6 +2354,56 @@ mod tests {
         assert_eq!(super::parse_cents(Some(" $1211,231.74 "), || "Test: ".to_string()), Err("Test: Pesos value has too many digits before the first ','. Is the value $1211,231.74 correctly formatted as pesos?".to_string()));
     }
     #[test]
+    fn cents10() {
+        assert_eq!(super::parse_cents_locale(Some("$ 1.234.567,89"), &super::Locale::COLOMBIA, || "Test: ".to_string()), Ok(123456789));
+    }
+    #[test]
+    fn cents11() {
+        assert_eq!(super::parse_cents_locale(Some("($ 1.234)"), &super::Locale::COLOMBIA, || "Test: ".to_string()), Ok(-123400));
+    }
+    #[test]
+    fn cents12() {
+        assert_eq!(super::parse_cents_locale(Some("$5"), &super::Locale::COLOMBIA, || "Test: ".to_string()), Ok(500));
+    }
+    #[test]
+    fn cents13() {
+        assert_eq!(super::parse_cents_locale(Some("$ 1.23"), &super::Locale::COLOMBIA, || "Test: ".to_string()), Err("Test: Pesos value has 2 digits after the last '.' instead of 3. Is the value $ 1.23 correctly formatted as pesos?".to_string()));
+    }
+    #[test]
+    fn cents14() {
+        assert_eq!(super::parse_cents_locale(Some("$ 1,234.56"), &super::Locale::COLOMBIA, || "Test: ".to_string()), Err("Test: Pesos value has '.' instead of a decimal digit. Is the value $ 1,234.56 correctly formatted as pesos?".to_string()));
+    }
+    #[test]
+    fn cents15() {
+        assert_eq!(super::parse_cents_locale(Some("1.234,56 $"), &super::Locale { symbol_after: true, ..super::Locale::COLOMBIA }, || "Test: ".to_string()), Ok(123456));
+    }
+    #[test]
+    fn cents16() {
+        assert_eq!(super::parse_cents(Some("($1,234.56)"), || "Test: ".to_string()), Err("Test: Value has no $ sign. Is the value ($1,234.56) correctly formatted as pesos?".to_string()));
+    }
+    #[test]
+    fn cents17() {
+        assert_eq!(super::parse_cents(Some("$1,234"), || "Test: ".to_string()), Err("Test: Pesos value has no '.' and 2 decimal digits. Is the value $1,234 correctly formatted as pesos?".to_string()));
+    }
+    #[test]
+    fn cents18() {
+        assert_eq!(super::parse_cents_locale(Some("$1.234."), &super::Locale::COLOMBIA, || "Test: ".to_string()), Err("Test: Pesos value has 0 digits after the last '.' instead of 3. Is the value $1.234. correctly formatted as pesos?".to_string()));
+    }
+    #[test]
+    fn locale0() {
+        assert_eq!("ES-CO".parse(), Ok(super::Locale::COLOMBIA));
+        assert_eq!("fr".parse::<super::Locale>(), Err("Locale fr not recognized; use bank or es-co".to_string()));
+        assert_eq!(super::parse_options(vec!["--locale".to_string(), "es-co".to_string()]).unwrap().locale, super::Locale::COLOMBIA);
+    }
+    #[test]
+    fn format0() {
+        assert_eq!(super::format_cents(0), "$0.00");
+        assert_eq!(super::format_cents(174), "$1.74");
+        assert_eq!(super::format_cents(921123174), "$9,211,231.74");
+        assert_eq!(super::format_cents(-21123105), "-$211,231.05");
+        assert_eq!(super::format_cents(-5), "-$0.05");
+    }
+    #[test]
     fn date0() {
         assert_eq!(super::parse_date_opt(None, || "Test: ".to_string()), Err("Test: No valid date".to_string()));
     }
@@ -1419,4 +2471,50 @@ mod tests {
     fn percent12() {
         assert_eq!(super::parse_percent(Some(".003 %EA"), || "Test: ".to_string()), Ok(0.003));
     }
+    #[test]
+    fn percent13() {
+        assert_eq!(super::parse_percent_locale(Some("12,5 %"), &super::Locale::COLOMBIA, || "Test: ".to_string()), Ok(super::Percent::Value(125, 1)));
+    }
+    #[test]
+    fn percent14() {
+        assert_eq!(super::parse_percent_locale(Some("-1.324,5 %EA"), &super::Locale::COLOMBIA, || "Test: ".to_string()), Ok(super::Percent::Value(-13245, 1)));
+    }
+    #[test]
+    fn percent15() {
+        assert_eq!(super::parse_percent_locale(Some("(0,25 %)"), &super::Locale::COLOMBIA, || "Test: ".to_string()), Ok(super::Percent::Value(-25, 2)));
+    }
+    #[test]
+    fn percent16() {
+        assert_eq!(super::parse_percent_locale(Some("1000,5 %"), &super::Locale::COLOMBIA, || "Test: ".to_string()), Err("Test: Percentage has too many digits before the first '.'. Is the value 1000,5 % correctly formatted as a percentage?".to_string()));
+    }
+    #[test]
+    fn percent17() {
+        assert_eq!(super::parse_percent_locale(Some("NA"), &super::Locale::COLOMBIA, || "Test: ".to_string()), Ok(super::Percent::NotAvailable));
+    }
+    #[test]
+    fn percent18() {
+        let reported = |percent: &str| super::parse_percent_locale(Some(percent), &super::Locale::BANK, || "Test: ".to_string()).unwrap().to_string();
+        assert_eq!(reported("-0.003 %EA"), "-0.003");
+        assert_eq!(reported("-.003 %EA"), "-0.003");
+        assert_eq!(reported("1.50 %"), "1.50");
+        assert_eq!(reported("-3,324,122,432.643 %EA"), "-3324122432.643");
+        assert_eq!(reported("3 %EA"), "3");
+        assert_eq!(reported("NA"), "NA");
+    }
+    #[test]
+    fn comparison0() {
+        // Closed funds are left out of comparison.csv
+        let date = |day| chrono::NaiveDate::from_ymd_opt(2021, 11, day).unwrap();
+        let series = |fund: &str, closed| super::Series {
+            fund: fund.to_string(),
+            balance: vec![super::Balance { date: date(1), balance: 100, provenance: None }, super::Balance { date: date(2), balance: 150, provenance: None }],
+            action: vec![],
+            fund_value: vec![],
+            currency: super::Currency::Cop,
+            fee: vec![],
+            closed,
+        };
+        let table = super::Table { table: vec![series("capital", None), series("estable", Some(date(3)))], investor: vec![], transfer: vec![], reviewed: vec![], import: vec![] };
+        assert_eq!(super::comparison_csv(&table), "Fund,Previous date,Previous $,Change,Last date,Last $\ncapital,2021-11-01,1,0.5,2021-11-02,1.5\n");
+    }
 }
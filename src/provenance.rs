@@ -0,0 +1,140 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Provenance of the stored records: the bank export and line each record came from, and when and by what it was imported.
+//!
+//! Each run registers an Import per export in Table::import; records refer to it by index.
+//! Imports that end up without records are removed before saving, so that runs without new data leave the database unchanged.
+
+use crate::Table;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
+/// Where a record came from.
+pub struct Provenance {
+    /// Index in Table::import.
+    pub import: u32,
+    /// Line of the file, starting at 1.
+    pub line: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+/// A bank export read by an importer.
+pub struct Import {
+    pub file: String,
+    /// FNV-1a hash of the content of the file, to tell apart exports with the same name.
+    pub hash: u64,
+    pub time: chrono::NaiveDateTime,
+    /// Program and version that imported the file.
+    pub importer: String,
+}
+
+/// FNV-1a hash, which is stable across versions of Rust, unlike the hasher of the standard library.
+pub fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Registers an import of a file in the table. Returns its index for Provenance::import.
+pub fn register(table: &mut Table, file_name: &str) -> Result<u32, String> {
+    let bytes = std::fs::read(file_name).map_err(|e| format!("Error reading the file {}: {}", file_name, e))?;
+    table.import.push(Import {
+        file: file_name.to_string(),
+        hash: hash(&bytes),
+        time: chrono::Local::now().naive_local(),
+        importer: format!("fondos {}", env!("CARGO_PKG_VERSION")),
+    });
+    Ok(table.import.len() as u32 - 1)
+}
+
+/// Provenance of every record of the table, to renumber the imports.
+fn provenances(table: &mut Table) -> impl Iterator<Item = &mut Provenance> {
+    table.table.iter_mut().flat_map(|s| {
+        s.balance
+            .iter_mut()
+            .filter_map(|b| b.provenance.as_mut())
+            .chain(s.action.iter_mut().filter_map(|a| a.provenance.as_mut()))
+            .chain(s.fund_value.iter_mut().filter_map(|v| v.provenance.as_mut()))
+    })
+}
+
+/// Removes the imports that no record refers to, renumbering the others.
+pub fn compact(table: &mut Table) {
+    let mut used = vec![false; table.import.len()];
+    for p in provenances(table) {
+        used[p.import as usize] = true;
+    }
+    let mut new_index = Vec::with_capacity(used.len());
+    let mut n = 0;
+    for u in used.iter() {
+        new_index.push(n);
+        if *u {
+            n += 1;
+        }
+    }
+    for p in provenances(table) {
+        p.import = new_index[p.import as usize];
+    }
+    let mut i = 0;
+    table.import.retain(|_| {
+        i += 1;
+        used[i - 1]
+    });
+}
+
+/// Describes where a record came from, for queries. Empty for records recorded by hand or imported before provenance was recorded.
+pub fn describe(table: &Table, provenance: Option<Provenance>) -> String {
+    match provenance.and_then(|p| table.import.get(p.import as usize).map(|i| (p, i))) {
+        Some((p, i)) => format!("{} line {}, imported {} by {}, hash {:016x}", i.file, p.line, i.time.format("%Y-%m-%d %H:%M:%S"), i.importer, i.hash),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Import, Provenance};
+    use crate::{Balance, Currency, Series, Table};
+    #[test]
+    fn provenance0() {
+        assert_eq!(super::hash(b""), 0xcbf29ce484222325);
+        assert_eq!(super::hash(b"a"), 0xaf63dc4c8601ec8c);
+    }
+    #[test]
+    fn provenance1() {
+        let date = chrono::NaiveDate::from_ymd_opt(2021, 11, 19).unwrap();
+        let import = |file: &str| Import { file: file.to_string(), hash: 1, time: date.and_hms_opt(8, 0, 0).unwrap(), importer: "fondos 0.1.0".to_string() };
+        let mut table = Table {
+            table: vec![Series {
+                fund: "capital".to_string(),
+                balance: vec![Balance { date, balance: 100, provenance: Some(Provenance { import: 2, line: 7 }) }, Balance { date, balance: 100, provenance: None }],
+                action: vec![],
+                fund_value: vec![],
+                currency: Currency::Cop,
+                fee: vec![],
+                closed: None,
+            }],
+            investor: vec!["Default investor".to_string()],
+            transfer: vec![],
+            reviewed: vec![],
+            import: vec![import("balances.txt"), import("history.txt"), import("profit.txt")],
+        };
+        super::compact(&mut table);
+        assert_eq!(table.import, vec![import("profit.txt")]);
+        let provenance = table.table[0].balance[0].provenance;
+        assert_eq!(super::describe(&table, provenance), "profit.txt line 7, imported 2021-11-19 08:00:00 by fondos 0.1.0, hash 0000000000000001");
+        assert_eq!(super::describe(&table, None), "");
+    }
+}
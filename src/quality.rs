@@ -0,0 +1,158 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Data-quality audit of the stored history, for command audit.
+
+use crate::{format_cents, Series, Table};
+use chrono::Datelike;
+
+#[derive(Clone, Debug, PartialEq)]
+/// Limits beyond which the audit reports a finding.
+pub struct Thresholds {
+    /// Maximum number of business days between two balances.
+    pub max_gap: i64,
+    /// Maximum change in percentage of a balance beyond its actions, and of the unit value in one day.
+    pub max_change: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds { max_gap: 5, max_change: 5. }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// Problem found in the records of a fund.
+pub struct Finding {
+    pub fund: String,
+    pub date: Option<chrono::NaiveDate>,
+    pub description: String,
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.date {
+            Some(date) => write!(f, "{} {}: {}", self.fund, date, self.description),
+            None => write!(f, "{}: {}", self.fund, self.description),
+        }
+    }
+}
+
+/// Number of business days after start, up to and including end. Holidays are counted as business days.
+pub fn business_days(start: chrono::NaiveDate, end: chrono::NaiveDate) -> i64 {
+    start.iter_days().skip(1).take_while(|d| *d <= end).filter(|d| d.weekday().number_from_monday() <= 5).count() as i64
+}
+
+/// Audits the records of a fund. Expects the records sorted by date.
+pub fn audit_series(series: &Series, thresholds: &Thresholds) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut finding = |date, description: String| findings.push(Finding { fund: series.fund.clone(), date, description });
+    if series.balance.is_empty() && !series.action.is_empty() {
+        finding(None, format!("{} actions but no balances", series.action.len()));
+    }
+    for b in series.balance.iter().filter(|b| b.balance < 0) {
+        finding(Some(b.date), format!("negative balance {}", format_cents(b.balance)));
+    }
+    for pair in series.balance.windows(2) {
+        let (previous, current) = (&pair[0], &pair[1]);
+        if previous.date == current.date {
+            finding(Some(current.date), format!("duplicate balance: {} and {}", format_cents(previous.balance), format_cents(current.balance)));
+            continue;
+        }
+        let gap = business_days(previous.date, current.date);
+        if gap > thresholds.max_gap {
+            finding(Some(current.date), format!("{} business days since the previous balance on {}", gap, previous.date));
+        }
+        // Change of the balance that the actions in between do not explain
+        let actions: Vec<_> = series.action.iter().filter(|a| a.date > previous.date && a.date <= current.date).collect();
+        let action_sum: crate::Cents = actions.iter().map(|a| a.change).sum();
+        if previous.balance != 0 {
+            let change = 100. * (current.balance - previous.balance - action_sum) as f64 / previous.balance.abs() as f64;
+            if change.abs() > thresholds.max_change {
+                let cause = if actions.is_empty() { "without an action".to_string() } else { format!("beyond actions of {}", format_cents(action_sum)) };
+                finding(Some(current.date), format!("balance changes {:.2}% from {} on {} {}", change, format_cents(previous.balance), previous.date, cause));
+            }
+        }
+    }
+    for pair in series.fund_value.windows(2) {
+        let (previous, current) = (&pair[0], &pair[1]);
+        if previous.date == current.date {
+            finding(Some(current.date), format!("duplicate fund value: unit values {} and {}", format_cents(previous.unit_value), format_cents(current.unit_value)));
+            continue;
+        }
+        if previous.unit_value > 0 && (current.date - previous.date).num_days() == 1 {
+            let change = 100. * (current.unit_value - previous.unit_value) as f64 / previous.unit_value as f64;
+            if change.abs() > thresholds.max_change {
+                finding(Some(current.date), format!("unit value changes {:.2}% in one day, from {} to {}", change, format_cents(previous.unit_value), format_cents(current.unit_value)));
+            }
+        }
+    }
+    findings
+}
+
+/// Audits every fund of the table.
+pub fn audit(table: &Table, thresholds: &Thresholds) -> Vec<Finding> {
+    table.table.iter().flat_map(|s| audit_series(s, thresholds)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Action, ActionKind, Balance, Currency, FundValue, Series};
+    fn date(day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2021, 11, day).unwrap()
+    }
+    fn series(balance: &[(u32, i64)], action: &[(u32, i64)], unit_value: &[(u32, i64)]) -> Series {
+        Series {
+            fund: "capital".to_string(),
+            balance: balance.iter().map(|(day, balance)| Balance { date: date(*day), balance: *balance, provenance: None }).collect(),
+            action: action.iter().map(|(day, change)| Action { date: date(*day), change: *change, investor: 0, kind: ActionKind::Flow, source: None, provenance: None }).collect(),
+            fund_value: unit_value.iter().map(|(day, unit_value)| FundValue { date: date(*day), fund_value: 0, unit_value: *unit_value, provenance: None }).collect(),
+            currency: Currency::Cop,
+            fee: vec![],
+            closed: None,
+        }
+    }
+    #[test]
+    fn quality0() {
+        // Friday 19 to Monday 22 is one business day
+        assert_eq!(super::business_days(date(19), date(22)), 1);
+        assert_eq!(super::business_days(date(1), date(30)), 21);
+        let thresholds = super::Thresholds::default();
+        assert!(super::audit_series(&series(&[(19, 1000), (22, 1010)], &[], &[(19, 100), (20, 101)]), &thresholds).is_empty());
+        assert_eq!(super::audit_series(&series(&[], &[(3, 100)], &[]), &thresholds)[0].to_string(), "capital: 1 actions but no balances");
+    }
+    #[test]
+    fn quality1() {
+        let thresholds = super::Thresholds { max_gap: 5, max_change: 5. };
+        let findings: Vec<_> = super::audit_series(&series(&[(1, 1000), (1, 1000), (15, 2000), (16, 3000), (17, -5)], &[(16, 1000)], &[(1, 100), (2, 110), (2, 110)]), &thresholds).iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            findings,
+            vec![
+                "capital 2021-11-17: negative balance -$0.05",
+                "capital 2021-11-01: duplicate balance: $10.00 and $10.00",
+                "capital 2021-11-15: 10 business days since the previous balance on 2021-11-01",
+                "capital 2021-11-15: balance changes 100.00% from $10.00 on 2021-11-01 without an action",
+                "capital 2021-11-17: balance changes -100.17% from $30.00 on 2021-11-16 without an action",
+                "capital 2021-11-02: unit value changes 10.00% in one day, from $1.00 to $1.10",
+                "capital 2021-11-02: duplicate fund value: unit values $1.10 and $1.10",
+            ]
+        );
+        // A small action does not hide a large jump
+        let findings: Vec<_> = super::audit_series(&series(&[(1, 10000), (2, 15000)], &[(2, 100)], &[]), &thresholds).iter().map(|f| f.to_string()).collect();
+        assert_eq!(findings, vec!["capital 2021-11-02: balance changes 49.00% from $100.00 on 2021-11-01 beyond actions of $1.00"]);
+    }
+}
@@ -0,0 +1,298 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Listing of stored records with filters, for command show.
+
+use crate::{parse_cents, parse_date_opt, parse_name, ActionKind, Cents, Table};
+use serde::Serialize;
+
+pub const QUERY_USAGE: &str = "fondos show <balances|actions|fund-values> [--fund <name or glob>] [--from <d/m/y>] [--to <d/m/y>] [--kind <flow|fee>] [--investor <name or index>] [--min-amount <$amount>] [--output <text|csv|json>] [--source]";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Record {
+    Balances,
+    Actions,
+    FundValues,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Output {
+    Text,
+    Csv,
+    Json,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// Filters of command show. Filters --kind and --investor apply to actions only.
+pub struct Query {
+    pub record: Record,
+    /// Fund name, where * matches any text and ? matches any character.
+    pub fund: Option<String>,
+    pub from: Option<chrono::NaiveDate>,
+    pub to: Option<chrono::NaiveDate>,
+    pub kind: Option<ActionKind>,
+    /// Investor name or index.
+    pub investor: Option<String>,
+    /// Minimum absolute amount: balance, change of an action, or value of the whole fund.
+    pub min_amount: Option<Cents>,
+    pub output: Output,
+    /// Show the bank export each record came from.
+    pub source: bool,
+}
+
+#[derive(Serialize)]
+/// A matching record, for JSON output. Amounts are expressed in cents.
+struct Match<'a> {
+    fund: &'a str,
+    date: chrono::NaiveDate,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    balance: Option<Cents>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    change: Option<Cents>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<ActionKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    investor: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fund_value: Option<Cents>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit_value: Option<Cents>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+}
+
+/// Whether text matches a pattern in which * matches any text and ? matches any character.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last * in the pattern, and the position in the text it was tried at
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Parses the arguments of command show, after the word show.
+pub fn parse_query<I>(args_it: &mut I) -> Result<Query, String>
+where
+I: Iterator<Item = String>
+{
+    let record = match args_it.next().as_deref() {
+        Some("balances") => Record::Balances,
+        Some("actions") => Record::Actions,
+        Some("fund-values") => Record::FundValues,
+        _ => return Err(format!("Parsing records for show: Use balances, actions, or fund-values. Usage: {}", QUERY_USAGE)),
+    };
+    let mut query = Query { record, fund: None, from: None, to: None, kind: None, investor: None, min_amount: None, output: Output::Text, source: false };
+    while let Some(arg) = args_it.next() {
+        let error_prefix = || format!("Parsing option {} for show: ", arg);
+        match arg.as_str() {
+            "--fund" => query.fund = Some(parse_name(args_it.next().as_deref(), error_prefix)?.to_lowercase()),
+            "--from" => query.from = Some(parse_date_opt(args_it.next().as_deref(), error_prefix)?),
+            "--to" => query.to = Some(parse_date_opt(args_it.next().as_deref(), error_prefix)?),
+            "--kind" => {
+                query.kind = match args_it.next().as_deref() {
+                    Some("flow") => Some(ActionKind::Flow),
+                    Some("fee") => Some(ActionKind::FeeCharge),
+                    _ => return Err(format!("{}Use flow or fee", error_prefix())),
+                }
+            }
+            "--investor" => query.investor = Some(parse_name(args_it.next().as_deref(), error_prefix)?.to_string()),
+            "--source" => query.source = true,
+            "--min-amount" => query.min_amount = Some(parse_cents(args_it.next().as_deref(), error_prefix)?),
+            "--output" => {
+                query.output = match args_it.next().as_deref() {
+                    Some("text") => Output::Text,
+                    Some("csv") => Output::Csv,
+                    Some("json") => Output::Json,
+                    _ => return Err(format!("{}Use text, csv, or json", error_prefix())),
+                }
+            }
+            _ => return Err(format!("Unknown option {} for show. Usage: {}", arg, QUERY_USAGE)),
+        }
+    }
+    if query.record != Record::Actions && (query.kind.is_some() || query.investor.is_some()) {
+        return Err("Parsing options for show: --kind and --investor apply to actions only".to_string());
+    }
+    Ok(query)
+}
+
+pub fn kind_name(kind: ActionKind) -> &'static str {
+    match kind {
+        ActionKind::Flow => "flow",
+        ActionKind::FeeCharge => "fee",
+    }
+}
+
+impl Query {
+    /// Lists the matching records, sorted by fund and date.
+    pub fn run(&self, table: &Table) -> Result<String, String> {
+        let investor = match &self.investor {
+            None => None,
+            Some(name) => Some(
+                table
+                    .investor
+                    .iter()
+                    .position(|i| i.to_lowercase() == name.to_lowercase())
+                    .or_else(|| name.parse::<usize>().ok().filter(|i| *i < table.investor.len()))
+                    .ok_or_else(|| format!("Investor {} not found", name))?,
+            ),
+        };
+        let in_range = |date: chrono::NaiveDate| self.from.is_none_or(|d| date >= d) && self.to.is_none_or(|d| date <= d);
+        let large = |amount: Cents| self.min_amount.is_none_or(|m| amount.abs() >= m);
+        let mut funds: Vec<_> = table.table.iter().filter(|s| self.fund.as_ref().is_none_or(|f| glob_match(f, &s.fund))).collect();
+        funds.sort_by(|a, b| a.fund.cmp(&b.fund));
+        let empty = Match { fund: "", date: chrono::NaiveDate::MIN, balance: None, change: None, kind: None, investor: None, fund_value: None, unit_value: None, source: None };
+        let source = |provenance| if self.source { Some(crate::provenance::describe(table, provenance)) } else { None };
+        let mut matches = Vec::new();
+        for series in funds {
+            match self.record {
+                Record::Balances => matches.extend(series.balance.iter().filter(|b| in_range(b.date) && large(b.balance)).map(|b| Match { fund: &series.fund, date: b.date, balance: Some(b.balance), source: source(b.provenance), ..empty })),
+                Record::Actions => matches.extend(
+                    series
+                        .action
+                        .iter()
+                        .filter(|a| in_range(a.date) && large(a.change) && self.kind.is_none_or(|k| a.kind == k) && investor.is_none_or(|i| a.investor as usize == i))
+                        .map(|a| Match {
+                            fund: &series.fund,
+                            date: a.date,
+                            change: Some(a.change),
+                            kind: Some(a.kind),
+                            investor: Some(table.investor.get(a.investor as usize).map(String::as_str).unwrap_or("")),
+                            source: source(a.provenance),
+                            ..empty
+                        }),
+                ),
+                Record::FundValues => matches.extend(series.fund_value.iter().filter(|v| in_range(v.date) && large(v.fund_value)).map(|v| Match { fund: &series.fund, date: v.date, fund_value: Some(v.fund_value), unit_value: Some(v.unit_value), source: source(v.provenance), ..empty })),
+            }
+        }
+        matches.sort_by(|a, b| a.fund.cmp(b.fund).then(a.date.cmp(&b.date)));
+        let mut header: Vec<&str> = match self.record {
+            Record::Balances => vec!["Fund", "Date", "Balance"],
+            Record::Actions => vec!["Fund", "Date", "Change", "Kind", "Investor"],
+            Record::FundValues => vec!["Fund", "Date", "Fund value", "Unit value"],
+        };
+        if self.source {
+            header.push("Source");
+        }
+        let fields = |m: &Match, amount: fn(Cents) -> String| -> Vec<String> {
+            let mut fields = vec![m.fund.to_string(), m.date.to_string()];
+            fields.extend(m.balance.map(amount));
+            fields.extend(m.change.map(amount));
+            fields.extend(m.kind.map(|k| kind_name(k).to_string()));
+            fields.extend(m.investor.map(str::to_string));
+            fields.extend(m.fund_value.map(amount));
+            fields.extend(m.unit_value.map(amount));
+            fields.extend(m.source.clone());
+            fields
+        };
+        match self.output {
+            Output::Json => serde_json::to_string_pretty(&matches).map(|j| j + "\n").map_err(|e| format!("Error serializing records: {}", e)),
+            Output::Csv => {
+                let mut text = format!("{}\n", header.join(","));
+                for m in matches.iter() {
+                    // Sources contain commas
+                    let quoted: Vec<String> = fields(m, |c| (c as f64 / 100.0).to_string()).into_iter().map(|f| if f.contains(',') || f.contains('"') { format!("\"{}\"", f.replace('"', "\"\"")) } else { f }).collect();
+                    text.push_str(&format!("{}\n", quoted.join(",")));
+                }
+                Ok(text)
+            }
+            Output::Text => {
+                let rows: Vec<Vec<String>> = matches.iter().map(|m| fields(m, crate::format_cents)).collect();
+                let widths: Vec<usize> = (0..header.len()).map(|i| rows.iter().map(|r| r[i].chars().count()).chain(std::iter::once(header[i].len())).max().unwrap()).collect();
+                // Text columns align left, amounts align right
+                let align_right = |i: usize| header[i] != "Fund" && header[i] != "Date" && header[i] != "Kind" && header[i] != "Investor" && header[i] != "Source";
+                let line = |row: Vec<String>| -> String {
+                    let cells: Vec<String> = row.iter().enumerate().map(|(i, c)| if align_right(i) { format!("{:>w$}", c, w = widths[i]) } else { format!("{:<w$}", c, w = widths[i]) }).collect();
+                    format!("{}\n", cells.join("  ").trim_end())
+                };
+                let mut text = line(header.iter().map(|h| h.to_string()).collect());
+                for row in rows {
+                    text.push_str(&line(row));
+                }
+                text.push_str(&format!("{} records\n", matches.len()));
+                Ok(text)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Action, ActionKind, Balance, Currency, Series, Table};
+    fn date(day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2021, 11, day).unwrap()
+    }
+    fn table() -> Table {
+        let series = |fund: &str| Series {
+            fund: fund.to_string(),
+            balance: vec![Balance { date: date(1), balance: 100000, provenance: None }, Balance { date: date(10), balance: 50, provenance: None }],
+            action: vec![Action { date: date(2), change: -30000, investor: 1, kind: ActionKind::Flow, source: None, provenance: None }, Action { date: date(3), change: -500, investor: 0, kind: ActionKind::FeeCharge, source: None, provenance: None }],
+            fund_value: vec![],
+            currency: Currency::Cop,
+            fee: vec![],
+            closed: None,
+        };
+        Table { table: vec![series("renta fija pesos"), series("acciones global"), series("renta fija global")], investor: vec!["Default investor".to_string(), "Investor 1".to_string()], transfer: vec![], reviewed: vec![], import: vec![] }
+    }
+    fn query(args: &[&str]) -> Result<super::Query, String> {
+        super::parse_query(&mut args.iter().map(|a| a.to_string()))
+    }
+    #[test]
+    fn query0() {
+        assert!(super::glob_match("renta*", "renta fija pesos"));
+        assert!(super::glob_match("*fija*global", "renta fija global"));
+        assert!(super::glob_match("acciones ?lobal", "acciones global"));
+        assert!(!super::glob_match("renta*pesos", "renta fija global"));
+        assert_eq!(query(&["balances", "--kind", "fee"]).err(), Some("Parsing options for show: --kind and --investor apply to actions only".to_string()));
+        assert!(query(&["trades"]).is_err());
+    }
+    #[test]
+    fn query1() {
+        let text = query(&["balances", "--fund", "Renta*", "--min-amount", "$100.00"]).unwrap().run(&table()).unwrap();
+        assert_eq!(text, "Fund               Date          Balance\nrenta fija global  2021-11-01  $1,000.00\nrenta fija pesos   2021-11-01  $1,000.00\n2 records\n");
+        let csv = query(&["actions", "--fund", "acciones global", "--investor", "investor 1", "--output", "csv"]).unwrap().run(&table()).unwrap();
+        assert_eq!(csv, "Fund,Date,Change,Kind,Investor\nacciones global,2021-11-02,-300,flow,Investor 1\n");
+        let json = query(&["actions", "--kind", "fee", "--to", "02/11/2021", "--output", "json"]).unwrap().run(&table()).unwrap();
+        assert_eq!(json, "[]\n");
+        assert_eq!(query(&["actions", "--investor", "7"]).unwrap().run(&table()).err(), Some("Investor 7 not found".to_string()));
+    }
+    #[test]
+    fn query2() {
+        let mut table = table();
+        table.import.push(crate::provenance::Import { file: "balances.txt".to_string(), hash: 0xff, time: date(1).and_hms_opt(8, 0, 0).unwrap(), importer: "fondos 0.1.0".to_string() });
+        table.table[0].balance[0].provenance = Some(crate::provenance::Provenance { import: 0, line: 3 });
+        let csv = query(&["balances", "--fund", "renta fija pesos", "--output", "csv", "--source"]).unwrap().run(&table).unwrap();
+        assert_eq!(csv, "Fund,Date,Balance,Source\nrenta fija pesos,2021-11-01,1000,\"balances.txt line 3, imported 2021-11-01 08:00:00 by fondos 0.1.0, hash 00000000000000ff\"\nrenta fija pesos,2021-11-10,0.5,\n");
+        let json = query(&["balances", "--fund", "renta fija pesos", "--to", "01/11/2021", "--output", "json"]).unwrap().run(&table).unwrap();
+        assert!(!json.contains("source"));
+    }
+}
@@ -0,0 +1,155 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reconciliation of balances against actions and unit values, for command reconcile.
+//!
+//! Between two balance dates, the change of the balance should equal the sum of the actions plus the units times the change of the unit value.
+//! Each action buys or sells units at the unit value of its date, so the units change along the way.
+//! A residual far from zero points to a movement missing from history.txt, or to an error of the bank.
+
+use crate::{format_cents, Cents, FundValue, Series, Table};
+use std::io::Write as IoWrite;
+
+#[derive(Clone, Debug, PartialEq)]
+/// Reconciliation of a fund between two consecutive balance dates.
+pub struct Reconciliation {
+    pub fund: String,
+    pub start_date: chrono::NaiveDate,
+    pub end_date: chrono::NaiveDate,
+    pub start_balance: Cents,
+    pub end_balance: Cents,
+    /// Sum of the actions after start_date, up to and including end_date.
+    pub actions: Cents,
+    /// Change of the value of the units; None without unit values for the dates.
+    pub market: Option<Cents>,
+}
+
+impl Reconciliation {
+    /// Change of the balance that neither the actions nor the unit value explain.
+    pub fn residual(&self) -> Option<Cents> {
+        self.market.map(|m| self.end_balance - self.start_balance - self.actions - m)
+    }
+}
+
+/// Unit value on a date, or on the latest date before it.
+fn unit_value_on(fund_value: &[FundValue], date: chrono::NaiveDate) -> Option<f64> {
+    fund_value.iter().rev().find(|v| v.date <= date && v.unit_value > 0).map(|v| v.unit_value as f64)
+}
+
+/// Reconciles every consecutive pair of balance dates of a fund. Expects the records sorted by date.
+pub fn reconcile_series(series: &Series) -> Vec<Reconciliation> {
+    series
+        .balance
+        .windows(2)
+        .filter(|pair| pair[0].date < pair[1].date)
+        .map(|pair| {
+            let (start, end) = (&pair[0], &pair[1]);
+            let actions: Vec<_> = series.action.iter().filter(|a| a.date > start.date && a.date <= end.date).collect();
+            let market = (|| {
+                let start_unit_value = unit_value_on(&series.fund_value, start.date)?;
+                let end_unit_value = unit_value_on(&series.fund_value, end.date)?;
+                // Units held since start_date, plus the units of each action from its date
+                let mut market = start.balance as f64 / start_unit_value * (end_unit_value - start_unit_value);
+                for a in actions.iter() {
+                    market += a.change as f64 * (end_unit_value / unit_value_on(&series.fund_value, a.date)? - 1.);
+                }
+                Some(market.round() as Cents)
+            })();
+            Reconciliation {
+                fund: series.fund.clone(),
+                start_date: start.date,
+                end_date: end.date,
+                start_balance: start.balance,
+                end_balance: end.balance,
+                actions: actions.iter().map(|a| a.change).sum(),
+                market,
+            }
+        })
+        .collect()
+}
+
+/// Reconciles every fund of the table, or only fund if given.
+pub fn reconcile(table: &Table, fund: Option<&str>) -> Vec<Reconciliation> {
+    table.table.iter().filter(|s| fund.is_none_or(|f| s.fund == f)).flat_map(reconcile_series).collect()
+}
+
+/// Saves every reconciliation to a CSV file.
+pub fn write_csv(reconciliations: &[Reconciliation], csv_file_name: &str) -> Result<(), String> {
+    let csv_file = crate::create_file(csv_file_name)?;
+    let csv_err = |e| format!("Error writing to {}: {}", csv_file_name, e);
+    writeln!(&csv_file, "Fund,Start date,End date,Start balance,End balance,Actions,Market change,Residual").map_err(csv_err)?;
+    let pesos = |cents: Option<Cents>| cents.map(|c| (c as f64 / 100.0).to_string()).unwrap_or_default();
+    for r in reconciliations {
+        writeln!(&csv_file, "{},{},{},{},{},{},{},{}", r.fund, r.start_date, r.end_date, r.start_balance as f64 / 100.0, r.end_balance as f64 / 100.0, r.actions as f64 / 100.0, pesos(r.market), pesos(r.residual())).map_err(csv_err)?;
+    }
+    Ok(())
+}
+
+/// Printable list of the reconciliations with a residual larger than tolerance, or without unit values.
+pub fn summary(reconciliations: &[Reconciliation], tolerance: Cents) -> String {
+    let mut text = format!("{:<24} {:<10} {:<10} {:>20} {:>20} {:>20} {:>20}\n", "Fondo", "Desde", "Hasta", "Cambio del saldo", "Movimientos", "Mercado", "Residuo");
+    let mut n = 0;
+    for r in reconciliations.iter().filter(|r| r.residual().is_none_or(|residual| residual.abs() > tolerance)) {
+        n += 1;
+        let (market, residual) = match (r.market, r.residual()) {
+            (Some(market), Some(residual)) => (format_cents(market), format_cents(residual)),
+            _ => ("sin valor de unidad".to_string(), String::new()),
+        };
+        text.push_str(&format!("{:<24} {:<10} {:<10} {:>20} {:>20} {:>20} {:>20}\n", r.fund, r.start_date, r.end_date, format_cents(r.end_balance - r.start_balance), format_cents(r.actions), market, residual));
+    }
+    text.push_str(&format!("\n{} de {} periodos con un residuo mayor que {}.\n", n, reconciliations.len(), format_cents(tolerance)));
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Action, ActionKind, Balance, Currency, FundValue, Series};
+    fn date(day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2021, 11, day).unwrap()
+    }
+    fn series(balance: &[(u32, i64)], action: &[(u32, i64)]) -> Series {
+        Series {
+            fund: "capital".to_string(),
+            balance: balance.iter().map(|(day, balance)| Balance { date: date(*day), balance: *balance, provenance: None }).collect(),
+            action: action.iter().map(|(day, change)| Action { date: date(*day), change: *change, investor: 0, kind: ActionKind::Flow, source: None, provenance: None }).collect(),
+            fund_value: vec![FundValue { date: date(1), fund_value: 0, unit_value: 1000, provenance: None }, FundValue { date: date(2), fund_value: 0, unit_value: 2000, provenance: None }, FundValue { date: date(3), fund_value: 0, unit_value: 2200, provenance: None }],
+            currency: Currency::Cop,
+            fee: vec![],
+            closed: None,
+        }
+    }
+    #[test]
+    fn reconcile0() {
+        // 10 units at $10.00, plus $20.00 on day 2 buys 1 unit at $20.00; 11 units are worth $242.00 on day 3
+        let r = super::reconcile_series(&series(&[(1, 10000), (3, 24200)], &[(2, 2000)]));
+        assert_eq!((r[0].actions, r[0].market, r[0].residual()), (2000, Some(12200), Some(0)));
+        // A missing withdrawal of $22.00 on day 3
+        let r = super::reconcile_series(&series(&[(1, 10000), (3, 22000)], &[(2, 2000)]));
+        assert_eq!(r[0].residual(), Some(-2200));
+        assert!(super::summary(&r, 100).contains("-$22.00"));
+        assert!(super::summary(&r, 10000).ends_with("0 de 1 periodos con un residuo mayor que $100.00.\n"));
+    }
+    #[test]
+    fn reconcile1() {
+        let mut s = series(&[(1, 10000), (2, 20000), (2, 20000)], &[]);
+        s.fund_value.clear();
+        let r = super::reconcile_series(&s);
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].residual(), None);
+        assert!(super::summary(&r, 100).contains("sin valor de unidad"));
+    }
+}
@@ -0,0 +1,245 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Self-contained HTML report, report.html, with sortable tables, SVG charts for each duration, and a section per fund.
+//!
+//! The report has no external resources: styles, scripts, and charts are inline, so it opens in any browser without a server.
+
+use crate::{format_cents, risk, ActionKind, FundAggregate, Percent, Series, Table};
+use std::io::Write as IoWrite;
+
+/// Colors of the series, in the order of the palette of the figures.
+const PALETTE: &[&str] = &[
+    "#ffffff", "#ffc000", "#00b050", "#849c64", "#ffe792", "#9d550f", "#c43535", "#9e8ae3", "#86cad9", "#00c7c4",
+    "#808080", "#a08200", "#008c3c", "#506743", "#918f56", "#59380f", "#643535", "#4e649d", "#4e90bc", "#006e8c",
+];
+
+const WIDTH: f64 = 900.;
+const HEIGHT: f64 = 360.;
+const MARGIN_LEFT: f64 = 90.;
+const MARGIN_RIGHT: f64 = 20.;
+const MARGIN_Y: f64 = 30.;
+
+const STYLE: &str = "body { background: #000; color: #fff; font-family: Calibri, Carlito, sans-serif; margin: 2em; }
+a { color: #86cad9; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { border: 1px solid #333; padding: 0.2em 0.6em; }
+td.number { text-align: right; }
+table.sortable th { cursor: pointer; background: #222; }
+.charts { display: flex; flex-wrap: wrap; gap: 1em; }
+.legend { list-style: none; padding: 0; columns: 2; }
+.swatch { display: inline-block; width: 0.8em; height: 0.8em; margin-right: 0.4em; }
+svg text { fill: #fff; font-size: 12px; }";
+
+/// Sorts a table by the clicked column; numbers sort by the value in attribute data-value.
+const SCRIPT: &str = "document.querySelectorAll('table.sortable th').forEach(function (th) {
+    th.addEventListener('click', function () {
+        var table = th.closest('table');
+        var body = table.tBodies[0];
+        var column = th.cellIndex;
+        var ascending = th.dataset.order !== 'ascending';
+        th.dataset.order = ascending ? 'ascending' : 'descending';
+        var key = function (row) {
+            var cell = row.cells[column];
+            var value = cell.dataset.value;
+            return value === undefined ? cell.textContent : parseFloat(value);
+        };
+        Array.from(body.rows).sort(function (a, b) {
+            var ka = key(a), kb = key(b);
+            var order = (typeof ka === 'number' && typeof kb === 'number') ? ka - kb : String(ka).localeCompare(String(kb));
+            if (isNaN(order)) {
+                order = isNaN(ka) ? 1 : -1;
+            }
+            return ascending ? order : -order;
+        }).forEach(function (row) { body.appendChild(row); });
+    });
+});";
+
+/// A line of a chart: name and values by date.
+pub type ChartSeries = (String, Vec<(chrono::NaiveDate, f64)>);
+
+/// Escapes text for HTML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Cell with a percentage as reported, sortable by its value. Unavailable percentages are shown as NA.
+fn percent_cell(percent: Percent) -> String {
+    match percent {
+        Percent::NotAvailable => "<td class=\"number\" data-value=\"NaN\">NA</td>".to_string(),
+        Percent::Value(..) => format!("<td class=\"number\" data-value=\"{}\">{}</td>", percent, percent),
+    }
+}
+
+/// Cell with an amount in pesos, sortable by its value.
+fn cents_cell(cents: crate::Cents) -> String {
+    format!("<td class=\"number\" data-value=\"{}\">{}</td>", cents, format_cents(cents))
+}
+
+/// Draws the series as an SVG line chart, followed by a legend with the last value of each series.
+pub fn svg_chart(title: &str, series: &[ChartSeries], value_format: fn(f64) -> String) -> String {
+    let points = || series.iter().flat_map(|s| s.1.iter());
+    let (min_date, max_date) = match (points().map(|p| p.0).min(), points().map(|p| p.0).max()) {
+        (Some(min_date), Some(max_date)) => (min_date, max_date),
+        _ => return format!("<figure><figcaption>{}</figcaption><p>Sin datos</p></figure>\n", escape(title)),
+    };
+    let mut min_value = points().map(|p| p.1).fold(f64::INFINITY, f64::min);
+    let mut max_value = points().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+    if max_value - min_value < 1e-9 {
+        min_value -= 1.;
+        max_value += 1.;
+    }
+    let days = ((max_date - min_date).num_days() as f64).max(1.);
+    let x = |date: chrono::NaiveDate| MARGIN_LEFT + (WIDTH - MARGIN_LEFT - MARGIN_RIGHT) * (date - min_date).num_days() as f64 / days;
+    let y = |value: f64| MARGIN_Y + (HEIGHT - 2. * MARGIN_Y) * (max_value - value) / (max_value - min_value);
+    let mut svg = format!("<figure><figcaption>{}</figcaption>\n<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n", escape(title), WIDTH, HEIGHT, WIDTH, HEIGHT);
+    for i in 0..=4 {
+        let value = min_value + (max_value - min_value) * i as f64 / 4.;
+        svg.push_str(&format!("<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#333\"/>", MARGIN_LEFT, y(value), WIDTH - MARGIN_RIGHT, y(value)));
+        svg.push_str(&format!("<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>\n", MARGIN_LEFT - 6., y(value) + 4., escape(&value_format(value))));
+    }
+    for date in &[min_date, max_date] {
+        svg.push_str(&format!("<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>\n", x(*date), HEIGHT - 8., date));
+    }
+    for (index, (name, values)) in series.iter().enumerate() {
+        let points: Vec<String> = values.iter().map(|(d, v)| format!("{:.1},{:.1}", x(*d), y(*v))).collect();
+        svg.push_str(&format!("<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"2\" points=\"{}\"><title>{}</title></polyline>\n", PALETTE[index % PALETTE.len()], points.join(" "), escape(name)));
+    }
+    svg.push_str("</svg>\n<ul class=\"legend\">\n");
+    for (index, (name, values)) in series.iter().enumerate() {
+        let last = values.last().map(|v| value_format(v.1)).unwrap_or_default();
+        svg.push_str(&format!("<li><span class=\"swatch\" style=\"background: {}\"></span>{} {}</li>\n", PALETTE[index % PALETTE.len()], escape(name), escape(&last)));
+    }
+    svg.push_str("</ul></figure>\n");
+    svg
+}
+
+/// Variation of the unit value of each fund since the first date on or after start_date, expressed in percentage.
+fn unit_value_variation(table: &Table, funds: &[&str], start_date: chrono::NaiveDate) -> Vec<ChartSeries> {
+    table
+        .table
+        .iter()
+        .filter(|s| funds.contains(&s.fund.as_str()))
+        .filter_map(|s| {
+            let values: Vec<_> = s.fund_value.iter().filter(|v| v.date >= start_date).collect();
+            let first = values.first()?.unit_value as f64;
+            Some((s.fund.clone(), values.iter().map(|v| (v.date, 100.0 * v.unit_value as f64 / first - 100.0)).collect()))
+        })
+        .collect()
+}
+
+/// Section with the balances, unit values, risk metrics, and recent actions of a fund.
+fn fund_section(series: &Series, investors: &[String], risk_free_rate: f64) -> String {
+    let mut html = format!("<section id=\"fund-{}\">\n<h2>{} ({})</h2>\n", anchor(&series.fund), escape(&series.fund), series.currency);
+    if let Some(r) = risk::risk_metrics(&series.fund_value, risk_free_rate) {
+        html.push_str(&format!(
+            "<p>Del {} al {}: rendimiento anual {:.2}%, volatilidad {:.2}%, caída máxima {:.2}%, Sharpe {}, Sortino {}.</p>\n",
+            r.start_date, r.end_date, r.annual_return, r.volatility, r.max_drawdown.percent, risk::format_ratio(r.sharpe, 2), risk::format_ratio(r.sortino, 2)
+        ));
+    }
+    html.push_str("<div class=\"charts\">\n");
+    let balance: Vec<_> = series.balance.iter().map(|b| (b.date, b.balance as f64 / 100.0)).collect();
+    html.push_str(&svg_chart("Saldo", &[(series.fund.clone(), balance)], |v| format!("${:.0}", v)));
+    let unit_value: Vec<_> = series.fund_value.iter().map(|v| (v.date, v.unit_value as f64 / 100.0)).collect();
+    html.push_str(&svg_chart("Valor de la unidad", &[(series.fund.clone(), unit_value)], |v| format!("{:.2}", v)));
+    html.push_str("</div>\n");
+    if !series.action.is_empty() {
+        html.push_str("<h3>Movimientos recientes</h3>\n<table class=\"sortable\">\n<thead><tr><th>Fecha</th><th>Valor</th><th>Tipo</th><th>Inversionista</th></tr></thead>\n<tbody>\n");
+        for a in series.action.iter().rev().take(20) {
+            let kind = match a.kind {
+                ActionKind::Flow => "Movimiento",
+                ActionKind::FeeCharge => "Comisión",
+            };
+            let investor = investors.get(a.investor as usize).map(String::as_str).unwrap_or("");
+            html.push_str(&format!("<tr><td>{}</td>{}<td>{}</td><td>{}</td></tr>\n", a.date, cents_cell(a.change), kind, escape(investor)));
+        }
+        html.push_str("</tbody>\n</table>\n");
+    }
+    html.push_str("</section>\n");
+    html
+}
+
+/// Identifier of a fund for links within the report.
+fn anchor(fund: &str) -> String {
+    fund.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '-' }).collect()
+}
+
+/// Writes the report, leaving out closed funds. Expects the full history in table, sorted by date.
+pub fn write_html(file_name: &str, table: &Table, aggregates: &[FundAggregate], funds: &[&str], durations: &[i64], date: chrono::NaiveDate, risk_free_rate: f64) -> Result<(), String> {
+    let mut html = format!("<!DOCTYPE html>\n<html lang=\"es\">\n<head>\n<meta charset=\"utf-8\">\n<title>Fondos {}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<h1>Fondos {}</h1>\n", date, STYLE, date);
+    html.push_str("<nav><ul>\n");
+    for series in table.table.iter().filter(|s| s.closed.is_none()) {
+        html.push_str(&format!("<li><a href=\"#fund-{}\">{}</a></li>\n", anchor(&series.fund), escape(&series.fund)));
+    }
+    html.push_str("</ul></nav>\n");
+    html.push_str("<h2>Rentabilidad</h2>\n<table class=\"sortable\">\n<thead><tr><th>Portafolio</th><th>Día %</th><th>Día %EA</th><th>Mes %</th><th>3 meses %</th><th>6 meses %</th><th>Año corrido %</th><th>Año %</th><th>Año pasado %</th><th>Hace 2 años %</th><th>Últimos 2 años %</th><th>Desde el inicio %</th></tr></thead>\n<tbody>\n");
+    for f in aggregates.iter().filter(|f| !table.table.iter().any(|s| s.fund == f.fund && s.closed.is_some())) {
+        html.push_str(&format!("<tr><td><a href=\"#fund-{}\">{}</a></td>", anchor(&f.fund), escape(&f.fund)));
+        for value in &[f.roe_day, f.roe_day_annualized, f.roe_month, f.roe_trimester, f.roe_semester, f.roe_year_to_date, f.roe_year, f.roe_last_year, f.roe_next_to_last_year, f.roe_2_years, f.roe_total] {
+            html.push_str(&percent_cell(*value));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</tbody>\n</table>\n");
+    html.push_str("<h2>Saldos</h2>\n<table class=\"sortable\">\n<thead><tr><th>Fondo</th><th>Fecha anterior</th><th>Saldo anterior</th><th>Cambio</th><th>Última fecha</th><th>Último saldo</th></tr></thead>\n<tbody>\n");
+    for series in table.table.iter().filter(|s| s.closed.is_none()) {
+        let mut it = series.balance.iter().rev();
+        if let Some(last) = it.next() {
+            html.push_str(&format!("<tr><td><a href=\"#fund-{}\">{}</a></td>", anchor(&series.fund), escape(&series.fund)));
+            match it.next() {
+                Some(previous) => html.push_str(&format!("<td>{}</td>{}{}", previous.date, cents_cell(previous.balance), cents_cell(last.balance - previous.balance))),
+                None => html.push_str("<td></td><td></td><td></td>"),
+            }
+            html.push_str(&format!("<td>{}</td>{}</tr>\n", last.date, cents_cell(last.balance)));
+        }
+    }
+    html.push_str("</tbody>\n</table>\n");
+    html.push_str("<h2>Valor de la unidad</h2>\n<div class=\"charts\">\n");
+    for duration in durations {
+        if let Some(start_date) = date.checked_sub_signed(chrono::Duration::days(*duration)) {
+            html.push_str(&svg_chart(&format!("Variación del valor de la unidad {} días", duration), &unit_value_variation(table, funds, start_date), |v| format!("{:.2}%", v)));
+        }
+    }
+    html.push_str("</div>\n");
+    for series in table.table.iter().filter(|s| s.closed.is_none()) {
+        html.push_str(&fund_section(series, &table.investor, risk_free_rate));
+    }
+    html.push_str(&format!("<script>\n{}\n</script>\n</body>\n</html>\n", SCRIPT));
+    let file = crate::create_file(file_name)?;
+    (&file).write_all(html.as_bytes()).map_err(|e| format!("Error writing to {}: {}", file_name, e))
+}
+
+#[cfg(test)]
+mod tests {
+    fn date(day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2021, 11, day).unwrap()
+    }
+    #[test]
+    fn report0() {
+        let svg = super::svg_chart("Saldo <total>", &[("capital".to_string(), vec![(date(1), 1.), (date(11), 3.)])], |v| format!("{:.2}", v));
+        assert!(svg.contains("Saldo &lt;total&gt;"));
+        assert!(svg.contains("points=\"90.0,330.0 880.0,30.0\""));
+        assert!(svg.contains("capital 3.00"));
+        assert!(super::svg_chart("Saldo", &[], |v| v.to_string()).contains("Sin datos"));
+    }
+    #[test]
+    fn report1() {
+        assert_eq!(super::anchor("diver. conservador"), "diver--conservador");
+        assert_eq!(super::percent_cell(crate::Percent::NotAvailable), "<td class=\"number\" data-value=\"NaN\">NA</td>");
+        assert_eq!(super::percent_cell(crate::Percent::Value(-3, 3)), "<td class=\"number\" data-value=\"-0.003\">-0.003</td>");
+    }
+}
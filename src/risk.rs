@@ -0,0 +1,305 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Risk metrics calculated from the history of unit values.
+
+use crate::{FundValue, Series};
+
+/// Average number of days in a year, used to annualize figures.
+const DAYS_PER_YEAR: f64 = 365.25;
+
+#[derive(Clone, Debug, PartialEq)]
+/// Largest fall of the unit value from a peak, to be stored in risk.csv.
+pub struct Drawdown {
+    /// Date of the peak before the fall
+    pub peak_date: chrono::NaiveDate,
+    /// Date of the lowest unit value after the peak
+    pub trough_date: chrono::NaiveDate,
+    /// First date in which the unit value returns to the peak; None if the fund has not recovered yet.
+    pub recovery_date: Option<chrono::NaiveDate>,
+    /// Fall from the peak to the trough, expressed in percentage. Zero or negative.
+    pub percent: f64,
+}
+
+impl Drawdown {
+    /// Days from the trough to the recovery; None if the fund has not recovered yet.
+    pub fn recovery_days(&self) -> Option<i64> {
+        self.recovery_date.map(|d| (d - self.trough_date).num_days())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// Risk metrics of a fund, to be stored in risk.csv.
+pub struct RiskMetrics {
+    /// Date of the first unit value
+    pub start_date: chrono::NaiveDate,
+    /// Date of the last unit value
+    pub end_date: chrono::NaiveDate,
+    /// Number of unit values
+    pub observations: usize,
+    /// Compound annual return, expressed in percentage.
+    pub annual_return: f64,
+    /// Annualized standard deviation of the returns, expressed in percentage.
+    pub volatility: f64,
+    /// Annualized deviation of the returns below the risk-free rate, expressed in percentage.
+    pub downside_deviation: f64,
+    pub max_drawdown: Drawdown,
+    /// Excess return over the risk-free rate per unit of volatility; NaN without volatility.
+    pub sharpe: f64,
+    /// Excess return over the risk-free rate per unit of downside deviation; NaN without returns below the risk-free rate.
+    pub sortino: f64,
+}
+
+/// Ratio of an excess return to a deviation; NaN if the deviation is zero, as in funds whose unit value never falls.
+fn ratio(excess_return: f64, deviation: f64) -> f64 {
+    if deviation == 0. {
+        f64::NAN
+    } else {
+        excess_return / deviation
+    }
+}
+
+/// Formats a ratio with a number of decimals, showing NaN as NA.
+pub fn format_ratio(value: f64, decimals: usize) -> String {
+    if value.is_nan() {
+        "NA".to_string()
+    } else {
+        format!("{:.*}", decimals, value)
+    }
+}
+
+/// Finds the largest fall of the unit value from a peak. Expects values sorted by date.
+pub fn max_drawdown(fund_value: &[FundValue]) -> Option<Drawdown> {
+    let first = fund_value.first()?;
+    let mut peak = first;
+    let mut worst = (first, first, 0.);
+    for v in fund_value.iter() {
+        if v.unit_value >= peak.unit_value {
+            peak = v;
+        } else {
+            let percent = 100.0 * v.unit_value as f64 / peak.unit_value as f64 - 100.0;
+            if percent < worst.2 {
+                worst = (peak, v, percent);
+            }
+        }
+    }
+    let (worst_peak, trough, percent) = worst;
+    Some(Drawdown {
+        peak_date: worst_peak.date,
+        trough_date: trough.date,
+        recovery_date: if percent < 0. {
+            fund_value
+                .iter()
+                .skip_while(|v| v.date <= trough.date)
+                .find(|v| v.unit_value >= worst_peak.unit_value)
+                .map(|v| v.date)
+        } else {
+            None
+        },
+        percent,
+    })
+}
+
+/// Calculates how far each value is below the highest previous value, expressed in percentage. Expects values sorted by date.
+pub fn underwater(values: &[(chrono::NaiveDate, f64)]) -> Vec<(chrono::NaiveDate, f64)> {
+    values
+        .iter()
+        .scan(f64::MIN, |peak, (date, value)| {
+            if *value > *peak {
+                *peak = *value;
+            }
+            Some((*date, 100.0 * value / *peak - 100.0))
+        })
+        .collect()
+}
+
+/// Calculates the value of the consolidated portfolio on every balance date from start_date, starting at 100.
+///
+/// Actions are discounted so that deposits and withdrawals do not count as gains or losses. Actions take effect in the balances after the date of the action.
+pub fn portfolio_index(table: &[Series], start_date: chrono::NaiveDate) -> Vec<(chrono::NaiveDate, f64)> {
+    let mut dates: Vec<_> = table
+        .iter()
+        .flat_map(|s| s.balance.iter().map(|b| b.date))
+        .filter(|d| *d >= start_date)
+        .collect();
+    dates.sort_unstable();
+    dates.dedup();
+    let value = |date: chrono::NaiveDate| -> i64 {
+        table
+            .iter()
+            .filter_map(|s| s.balance.iter().rev().find(|b| b.date <= date))
+            .map(|b| b.balance)
+            .sum()
+    };
+    let mut index_vec = Vec::with_capacity(dates.len());
+    let mut dates_it = dates.into_iter();
+    if let Some(first_date) = dates_it.next() {
+        let mut previous = (first_date, value(first_date), 100.0);
+        index_vec.push((first_date, 100.0));
+        for date in dates_it {
+            let (previous_date, previous_value, previous_index) = previous;
+            let current_value = value(date);
+            let flows: i64 = table
+                .iter()
+                .flat_map(|s| s.action.iter())
+                .filter(|a| a.is_flow() && a.date >= previous_date && a.date < date)
+                .map(|a| a.change)
+                .sum();
+            let index = if previous_value > 0 {
+                previous_index * (current_value - flows) as f64 / previous_value as f64
+            } else {
+                previous_index
+            };
+            index_vec.push((date, index));
+            previous = (date, current_value, index);
+        }
+    }
+    index_vec
+}
+
+/// Calculates the risk metrics of a fund. Expects values sorted by date. risk_free_rate is annual, expressed in percentage.
+///
+/// Returns None if there are less than 3 unit values, or if the values span less than a day.
+pub fn risk_metrics(fund_value: &[FundValue], risk_free_rate: f64) -> Option<RiskMetrics> {
+    if fund_value.len() < 3 || fund_value.iter().any(|v| v.unit_value <= 0) {
+        return None;
+    }
+    let first = fund_value.first().unwrap();
+    let last = fund_value.last().unwrap();
+    let days = (last.date - first.date).num_days();
+    if days < 1 {
+        return None;
+    }
+    let returns: Vec<f64> = fund_value
+        .windows(2)
+        .map(|w| w[1].unit_value as f64 / w[0].unit_value as f64 - 1.0)
+        .collect();
+    let years = days as f64 / DAYS_PER_YEAR;
+    let periods_per_year = returns.len() as f64 / years;
+    let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (returns.len() - 1) as f64;
+    let volatility = variance.sqrt() * periods_per_year.sqrt();
+    let risk_free_fraction = risk_free_rate / 100.0;
+    let risk_free_per_period = (1.0 + risk_free_fraction).powf(1.0 / periods_per_year) - 1.0;
+    let downside_variance = returns
+        .iter()
+        .map(|r| (r - risk_free_per_period).min(0.).powi(2))
+        .sum::<f64>()
+        / returns.len() as f64;
+    let downside_deviation = downside_variance.sqrt() * periods_per_year.sqrt();
+    let annual_return = (last.unit_value as f64 / first.unit_value as f64).powf(1.0 / years) - 1.0;
+    let excess_return = annual_return - risk_free_fraction;
+    Some(RiskMetrics {
+        start_date: first.date,
+        end_date: last.date,
+        observations: fund_value.len(),
+        annual_return: 100.0 * annual_return,
+        volatility: 100.0 * volatility,
+        downside_deviation: 100.0 * downside_deviation,
+        max_drawdown: max_drawdown(fund_value)?,
+        sharpe: ratio(excess_return, volatility),
+        sortino: ratio(excess_return, downside_deviation),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::FundValue;
+    fn values(unit_values: &[i64]) -> Vec<FundValue> {
+        let start = chrono::NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        unit_values
+            .iter()
+            .enumerate()
+            .map(|(i, unit_value)| FundValue {
+                date: start + chrono::Duration::days(i as i64),
+                fund_value: 0,
+                unit_value: *unit_value,
+                provenance: None,
+            })
+            .collect()
+    }
+    fn date(day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2021, 1, day).unwrap()
+    }
+    #[test]
+    fn drawdown0() {
+        let d = super::max_drawdown(&values(&[100, 110, 99, 88, 105, 110, 120])).unwrap();
+        assert_eq!((d.peak_date, d.trough_date, d.recovery_date), (date(2), date(4), Some(date(6))));
+        assert!((d.percent + 20.0).abs() < 1e-9);
+        assert_eq!(d.recovery_days(), Some(2));
+    }
+    #[test]
+    fn drawdown1() {
+        let d = super::max_drawdown(&values(&[100, 90, 95, 120, 100, 99])).unwrap();
+        assert_eq!((d.peak_date, d.trough_date, d.recovery_date), (date(4), date(6), None));
+        assert!((d.percent + 17.5).abs() < 1e-9);
+    }
+    #[test]
+    fn drawdown2() {
+        let d = super::max_drawdown(&values(&[100, 101, 102])).unwrap();
+        assert_eq!(d.percent, 0.);
+        assert_eq!(d.recovery_date, None);
+    }
+    #[test]
+    fn underwater0() {
+        let v: Vec<_> = values(&[100, 110, 99, 120]).iter().map(|v| (v.date, v.unit_value as f64)).collect();
+        let u: Vec<_> = super::underwater(&v).into_iter().map(|(_, p)| p).collect();
+        assert_eq!(u.len(), 4);
+        assert!((u[0]).abs() < 1e-9 && (u[1]).abs() < 1e-9 && (u[2] + 10.0).abs() < 1e-9 && (u[3]).abs() < 1e-9);
+    }
+    #[test]
+    fn portfolio0() {
+        use crate::{Action, Balance, Series};
+        let series = Series {
+            fund: "capital".to_string(),
+            balance: vec![Balance { date: date(1), balance: 1000, provenance: None }, Balance { date: date(3), balance: 2100, provenance: None }, Balance { date: date(4), balance: 1890, provenance: None }],
+            action: vec![Action { date: date(2), change: 1000, investor: 0, kind: crate::ActionKind::Flow, source: None, provenance: None }],
+            fund_value: vec![],
+            currency: crate::Currency::Cop,
+            fee: vec![],
+            closed: None,
+        };
+        let p = super::portfolio_index(&[series], date(1));
+        assert_eq!(p.iter().map(|x| x.0).collect::<Vec<_>>(), vec![date(1), date(3), date(4)]);
+        assert!((p[1].1 - 110.0).abs() < 1e-9);
+        assert!((p[2].1 - 99.0).abs() < 1e-9);
+    }
+    #[test]
+    fn risk0() {
+        assert_eq!(super::risk_metrics(&values(&[100, 101]), 0.), None);
+    }
+    #[test]
+    fn risk1() {
+        let r = super::risk_metrics(&values(&[100, 101, 100, 101, 100]), 0.).unwrap();
+        assert_eq!(r.observations, 5);
+        assert!(r.volatility > 0.);
+        assert!(r.downside_deviation > 0.);
+        assert!((r.annual_return).abs() < 1e-9);
+        assert!(r.sharpe.abs() < 1e-9);
+    }
+    #[test]
+    fn risk2() {
+        // A money-market fund without negative days has no downside deviation
+        let r = super::risk_metrics(&values(&[100000, 100010, 100030, 100040, 100060]), 0.).unwrap();
+        assert_eq!(r.downside_deviation, 0.);
+        assert!(r.sortino.is_nan());
+        assert!(r.sharpe.is_finite());
+        assert_eq!(super::format_ratio(r.sortino, 4), "NA");
+        assert!(super::risk_metrics(&values(&[100, 100, 100]), 0.).unwrap().sharpe.is_nan());
+        assert_eq!(super::format_ratio(1.23456, 2), "1.23");
+    }
+}
@@ -0,0 +1,388 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Local web server to browse the database with a web browser.
+//!
+//! The server listens on localhost only and rereads the database whenever the file changes.
+//! JSON endpoints, all under /api/, express amounts in cents and dates as YYYY-MM-DD:
+//! - /api/version: changes whenever the database changes; the front end polls it to refresh.
+//! - /api/funds: fund list with currency and last balance.
+//! - /api/balances, /api/actions, /api/fund_values: records of every fund, or of one fund with ?fund=<name>.
+//! - /api/aggregates: returns and risk metrics of every fund, calculated from the unit values.
+//! - /api/positions: balance of every fund split among investors; see tax::shares().
+
+use crate::{risk, storage, tax, Cents, Currency, Series, Table};
+use serde::Serialize;
+
+/// Page of the front end. It draws the charts with SVG and polls /api/version to refresh.
+const INDEX_HTML: &str = r##"<!DOCTYPE html>
+<html lang="es">
+<head>
+<meta charset="utf-8">
+<title>Fondos</title>
+<style>
+body { background: #000; color: #fff; font-family: Calibri, Carlito, sans-serif; margin: 2em; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { border: 1px solid #333; padding: 0.2em 0.6em; }
+td.number { text-align: right; }
+select { margin-bottom: 1em; }
+svg text { fill: #fff; font-size: 12px; }
+</style>
+</head>
+<body>
+<h1>Fondos</h1>
+<p id="status"></p>
+<h2>Rentabilidad</h2>
+<table id="aggregates"></table>
+<h2>Posiciones por inversionista</h2>
+<table id="positions"></table>
+<h2>Detalle</h2>
+<select id="fund"></select>
+<div id="charts"></div>
+<table id="actions"></table>
+<script>
+var palette = ['#ffffff', '#ffc000', '#00b050', '#849c64', '#ffe792', '#9d550f', '#c43535', '#9e8ae3', '#86cad9', '#00c7c4'];
+var version = null;
+function get(path) {
+    return fetch(path).then(function (response) { return response.json(); });
+}
+function pesos(cents) {
+    return (cents / 100).toLocaleString('en-US', { style: 'currency', currency: 'USD' });
+}
+function percent(value) {
+    return value === null ? 'NA' : value.toFixed(2);
+}
+function escape(text) {
+    return String(text).replace(/&/g, '&amp;').replace(/</g, '&lt;').replace(/>/g, '&gt;').replace(/"/g, '&quot;');
+}
+function fill(id, header, rows) {
+    var html = '<tr>' + header.map(function (h) { return '<th>' + escape(h) + '</th>'; }).join('') + '</tr>';
+    rows.forEach(function (row) {
+        html += '<tr>' + row.map(function (c) {
+            return typeof c === 'number' ? '<td class="number">' + c + '</td>' : '<td>' + escape(c) + '</td>';
+        }).join('') + '</tr>';
+    });
+    document.getElementById(id).innerHTML = html;
+}
+function chart(title, points, format) {
+    if (points.length === 0) {
+        return '<figure><figcaption>' + title + '</figcaption><p>Sin datos</p></figure>';
+    }
+    var width = 900, height = 360, left = 90, right = 20, margin = 30;
+    var times = points.map(function (p) { return Date.parse(p[0]); });
+    var values = points.map(function (p) { return p[1]; });
+    var minTime = Math.min.apply(null, times), maxTime = Math.max.apply(null, times);
+    var minValue = Math.min.apply(null, values), maxValue = Math.max.apply(null, values);
+    if (maxValue - minValue < 1e-9) { minValue -= 1; maxValue += 1; }
+    var x = function (t) { return left + (width - left - right) * (t - minTime) / Math.max(maxTime - minTime, 1); };
+    var y = function (v) { return margin + (height - 2 * margin) * (maxValue - v) / (maxValue - minValue); };
+    var svg = '<svg width="' + width + '" height="' + height + '">';
+    for (var i = 0; i <= 4; i++) {
+        var v = minValue + (maxValue - minValue) * i / 4;
+        svg += '<line x1="' + left + '" y1="' + y(v) + '" x2="' + (width - right) + '" y2="' + y(v) + '" stroke="#333"/>';
+        svg += '<text x="' + (left - 6) + '" y="' + (y(v) + 4) + '" text-anchor="end">' + format(v) + '</text>';
+    }
+    svg += '<text x="' + left + '" y="' + (height - 8) + '">' + points[0][0] + '</text>';
+    svg += '<text x="' + (width - right) + '" y="' + (height - 8) + '" text-anchor="end">' + points[points.length - 1][0] + '</text>';
+    svg += '<polyline fill="none" stroke="' + palette[1] + '" stroke-width="2" points="' +
+        points.map(function (p, i) { return x(times[i]) + ',' + y(p[1]); }).join(' ') + '"/></svg>';
+    return '<figure><figcaption>' + title + '</figcaption>' + svg + '</figure>';
+}
+function showFund() {
+    var fund = document.getElementById('fund').value;
+    if (!fund) { return; }
+    var query = '?fund=' + encodeURIComponent(fund);
+    Promise.all([get('/api/balances' + query), get('/api/fund_values' + query), get('/api/actions' + query)]).then(function (data) {
+        var balances = data[0].length ? data[0][0].balance : [];
+        var fundValues = data[1].length ? data[1][0].fund_value : [];
+        var actions = data[2].length ? data[2][0].action : [];
+        document.getElementById('charts').innerHTML =
+            chart('Saldo', balances.map(function (b) { return [b.date, b.balance / 100]; }), function (v) { return '$' + v.toFixed(0); }) +
+            chart('Valor de la unidad', fundValues.map(function (v) { return [v.date, v.unit_value / 100]; }), function (v) { return v.toFixed(2); });
+        fill('actions', ['Fecha', 'Valor', 'Tipo', 'Inversionista'], actions.slice().reverse().map(function (a) {
+            return [a.date, pesos(a.change), a.kind, a.investor];
+        }));
+    });
+}
+function refresh() {
+    get('/api/aggregates').then(function (aggregates) {
+        fill('aggregates', ['Fondo', 'Saldo', 'Fecha', '30 días %', '90 días %', '180 días %', '365 días %', 'Desde el inicio %', 'Volatilidad %', 'Caída máxima %'], aggregates.map(function (a) {
+            return [a.fund, pesos(a.balance), a.last_date || '', percent(a.return_30_days), percent(a.return_90_days), percent(a.return_180_days), percent(a.return_365_days), percent(a.return_total), percent(a.volatility), percent(a.max_drawdown)];
+        }));
+        var select = document.getElementById('fund');
+        var selected = select.value;
+        select.innerHTML = aggregates.map(function (a) { return '<option>' + escape(a.fund) + '</option>'; }).join('');
+        if (selected) { select.value = selected; }
+        showFund();
+    });
+    get('/api/positions').then(function (positions) {
+        fill('positions', ['Fondo', 'Inversionista', 'Aportes netos', 'Saldo'], positions.map(function (p) {
+            return [p.fund, p.investor, pesos(p.net_contributions), pesos(p.balance)];
+        }));
+    });
+}
+function poll() {
+    get('/api/version').then(function (v) {
+        if (v.version !== version) {
+            version = v.version;
+            document.getElementById('status').textContent = 'Actualizado ' + new Date().toLocaleString();
+            refresh();
+        }
+    });
+}
+document.getElementById('fund').addEventListener('change', showFund);
+poll();
+setInterval(poll, 5000);
+</script>
+</body>
+</html>
+"##;
+
+#[derive(Serialize)]
+struct FundSummary<'a> {
+    fund: &'a str,
+    currency: Currency,
+    first_date: Option<chrono::NaiveDate>,
+    last_date: Option<chrono::NaiveDate>,
+    balance: Cents,
+}
+
+#[derive(Serialize)]
+/// Returns and risk metrics of a fund, expressed in percentage. None when there are not enough unit values.
+struct Aggregate<'a> {
+    fund: &'a str,
+    last_date: Option<chrono::NaiveDate>,
+    balance: Cents,
+    return_30_days: Option<f64>,
+    return_90_days: Option<f64>,
+    return_180_days: Option<f64>,
+    return_365_days: Option<f64>,
+    return_total: Option<f64>,
+    volatility: Option<f64>,
+    max_drawdown: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct Position<'a> {
+    fund: &'a str,
+    investor: &'a str,
+    net_contributions: Cents,
+    balance: Cents,
+}
+
+/// Return of the unit value over the last days before the last unit value, expressed in percentage.
+fn window_return(series: &Series, days: i64) -> Option<f64> {
+    let last = series.fund_value.last()?;
+    let start_date = last.date - chrono::Duration::days(days);
+    let first = series.fund_value.iter().rev().find(|v| v.date <= start_date)?;
+    if first.unit_value <= 0 {
+        return None;
+    }
+    Some(100.0 * last.unit_value as f64 / first.unit_value as f64 - 100.0)
+}
+
+fn aggregate(series: &Series) -> Aggregate<'_> {
+    let metrics = risk::risk_metrics(&series.fund_value, 0.);
+    Aggregate {
+        fund: &series.fund,
+        last_date: series.balance.last().map(|b| b.date),
+        balance: series.balance.last().map_or(0, |b| b.balance),
+        return_30_days: window_return(series, 30),
+        return_90_days: window_return(series, 90),
+        return_180_days: window_return(series, 180),
+        return_365_days: window_return(series, 365),
+        return_total: match (series.fund_value.first(), series.fund_value.last()) {
+            (Some(first), Some(last)) if first.unit_value > 0 && first.date < last.date => Some(100.0 * last.unit_value as f64 / first.unit_value as f64 - 100.0),
+            _ => None,
+        },
+        volatility: metrics.as_ref().map(|m| m.volatility),
+        max_drawdown: metrics.as_ref().map(|m| m.max_drawdown.percent),
+    }
+}
+
+fn positions(table: &Table) -> Vec<Position<'_>> {
+    let n_investors = table.investor.len().max(1);
+    let mut positions = Vec::new();
+    for series in table.table.iter() {
+        let last_balance = match series.balance.last() {
+            Some(b) => b,
+            None => continue,
+        };
+        let shares = tax::shares(series, n_investors, last_balance.date);
+        for (investor, share) in shares.iter().enumerate() {
+            let net_contributions: Cents = series.action.iter().filter(|a| a.is_flow() && a.investor as usize == investor).map(|a| a.change).sum();
+            let balance = (last_balance.balance as f64 * share).round() as Cents;
+            if balance == 0 && net_contributions == 0 {
+                continue;
+            }
+            positions.push(Position {
+                fund: &series.fund,
+                investor: table.investor.get(investor).map(String::as_str).unwrap_or(""),
+                net_contributions,
+                balance,
+            });
+        }
+    }
+    positions
+}
+
+/// Decodes %XX sequences and '+' in a query value.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok()).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(h)) => {
+                decoded.push(h);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (b, _) => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Series selected by the query: the fund named in ?fund=<name>, or every fund.
+fn selected<'a>(table: &'a Table, query: Option<&str>) -> Vec<&'a Series> {
+    let fund = query.and_then(|q| q.split('&').find_map(|p| p.strip_prefix("fund="))).map(|f| percent_decode(f).trim().to_lowercase());
+    table.table.iter().filter(|s| fund.as_ref().is_none_or(|f| s.fund == *f)).collect()
+}
+
+/// Answers a request for a URL with the status code, content type, and body.
+pub fn respond(table: &Table, version: u64, url: &str) -> (u16, &'static str, String) {
+    let mut url_parts = url.splitn(2, '?');
+    let path = url_parts.next().unwrap_or("/");
+    let query = url_parts.next();
+    let json = |value: Result<String, serde_json::Error>| match value {
+        Ok(body) => (200, "application/json", body),
+        Err(e) => (500, "text/plain; charset=utf-8", format!("Error serializing data: {}", e)),
+    };
+    match path {
+        "/" | "/index.html" => (200, "text/html; charset=utf-8", INDEX_HTML.to_string()),
+        "/api/version" => json(serde_json::to_string(&serde_json::json!({ "version": version.to_string() }))),
+        "/api/funds" => json(serde_json::to_string(
+            &table
+                .table
+                .iter()
+                .map(|s| FundSummary {
+                    fund: &s.fund,
+                    currency: s.currency,
+                    first_date: s.balance.first().map(|b| b.date),
+                    last_date: s.balance.last().map(|b| b.date),
+                    balance: s.balance.last().map_or(0, |b| b.balance),
+                })
+                .collect::<Vec<_>>(),
+        )),
+        "/api/balances" => json(serde_json::to_string(&selected(table, query).iter().map(|s| serde_json::json!({ "fund": s.fund, "balance": s.balance })).collect::<Vec<_>>())),
+        "/api/actions" => json(serde_json::to_string(&selected(table, query).iter().map(|s| serde_json::json!({ "fund": s.fund, "action": s.action })).collect::<Vec<_>>())),
+        "/api/fund_values" => json(serde_json::to_string(&selected(table, query).iter().map(|s| serde_json::json!({ "fund": s.fund, "fund_value": s.fund_value })).collect::<Vec<_>>())),
+        "/api/aggregates" => json(serde_json::to_string(&table.table.iter().map(aggregate).collect::<Vec<_>>())),
+        "/api/positions" => json(serde_json::to_string(&positions(table))),
+        _ => (404, "text/plain; charset=utf-8", format!("Not found: {}", path)),
+    }
+}
+
+/// Reads the database, with fund names in lower case as in the rest of the program.
+fn load(funds_file_name: &str) -> Result<Table, String> {
+    let mut table = storage::load(funds_file_name)?;
+    table.table.iter_mut().for_each(|s| s.fund = s.fund.trim().to_lowercase());
+    Ok(table)
+}
+
+/// Serves the database on localhost until the program is interrupted.
+pub fn serve(funds_file_name: &str, port: u16) -> Result<(), String> {
+    let modified = || std::fs::metadata(funds_file_name).and_then(|m| m.modified()).ok();
+    let mut table = load(funds_file_name)?;
+    let mut table_modified = modified();
+    let mut version = crate::calculate_hash(&table);
+    let address = format!("127.0.0.1:{}", port);
+    let server = tiny_http::Server::http(&address).map_err(|e| format!("Error starting the server at {}: {}", address, e))?;
+    println!("Serving {} at http://{}/ ; Ctrl + C to stop.", funds_file_name, address);
+    for request in server.incoming_requests() {
+        if modified() != table_modified {
+            match load(funds_file_name) {
+                Ok(t) => {
+                    table = t;
+                    table_modified = modified();
+                    version = crate::calculate_hash(&table);
+                }
+                // The file may be in the middle of a save; keep serving the previous data
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        let (status, content_type, body) = if *request.method() == tiny_http::Method::Get {
+            respond(&table, version, request.url())
+        } else {
+            (405, "text/plain; charset=utf-8", "Only GET requests are supported".to_string())
+        };
+        let header = tiny_http::Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap();
+        if let Err(e) = request.respond(tiny_http::Response::from_string(body).with_status_code(status).with_header(header)) {
+            eprintln!("Error answering a request: {}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Action, ActionKind, Balance, Currency, FundValue, Series, Table};
+    fn date(day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2021, 11, day).unwrap()
+    }
+    fn table() -> Table {
+        Table {
+            table: vec![Series {
+                fund: "acciones global".to_string(),
+                balance: vec![Balance { date: date(1), balance: 1000, provenance: None }, Balance { date: date(5), balance: 1200, provenance: None }],
+                action: vec![Action { date: date(1), change: 1000, investor: 1, kind: ActionKind::Flow, source: None, provenance: None }],
+                fund_value: vec![FundValue { date: date(1), fund_value: 0, unit_value: 100, provenance: None }, FundValue { date: date(5), fund_value: 0, unit_value: 120, provenance: None }],
+                currency: Currency::Usd,
+                fee: vec![],
+                closed: None,
+            }],
+            investor: vec!["Default investor".to_string(), "Investor 1".to_string()],
+            transfer: vec![],
+            reviewed: vec![],
+            import: vec![],
+        }
+    }
+    #[test]
+    fn serve0() {
+        let table = table();
+        let (status, content_type, body) = super::respond(&table, 7, "/api/balances?fund=Acciones%20Global");
+        assert_eq!((status, content_type), (200, "application/json"));
+        assert_eq!(body, r#"[{"balance":[{"balance":1000,"date":"2021-11-01","provenance":null},{"balance":1200,"date":"2021-11-05","provenance":null}],"fund":"acciones global"}]"#);
+        assert_eq!(super::respond(&table, 7, "/api/balances?fund=capital").2, "[]");
+        assert_eq!(super::respond(&table, 7, "/api/version").2, r#"{"version":"7"}"#);
+        assert_eq!(super::respond(&table, 7, "/nothing").0, 404);
+        assert!(super::respond(&table, 7, "/").2.starts_with("<!DOCTYPE html>"));
+    }
+    #[test]
+    fn serve1() {
+        let table = table();
+        let positions = super::respond(&table, 7, "/api/positions").2;
+        assert_eq!(positions, r#"[{"fund":"acciones global","investor":"Investor 1","net_contributions":1000,"balance":1200}]"#);
+        let aggregates = super::respond(&table, 7, "/api/aggregates").2;
+        assert!(aggregates.contains(r#""return_total":20.0"#));
+        assert_eq!(super::percent_decode("diver.+conservador%2C%zz"), "diver. conservador,%zz");
+    }
+}
@@ -0,0 +1,237 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reading and writing of the database in data/funds.dat.
+//!
+//! The file starts with MAGIC and the format version as a little-endian u32, followed by the Table serialized with bincode.
+//! Files written before the format had a version contain only the Table, in the layout of module v0.
+//! bincode is not self-describing: every change to the stored structs must increase FORMAT_VERSION and keep the previous layout in a module to convert from.
+//! Those modules declare every type they store, so that later changes to the types of the crate do not alter them.
+
+use crate::{Action, ActionKind, Balance, Currency, FundValue, Series, Table};
+use std::fs;
+
+const MAGIC: &[u8; 8] = b"fondosdb";
+const FORMAT_VERSION: u32 = 1;
+
+/// Layout of the database before the format had a version.
+mod v0 {
+    use serde::Deserialize;
+    #[derive(Deserialize)]
+    pub struct Balance {
+        pub date: chrono::NaiveDate,
+        pub balance: crate::Cents,
+    }
+    #[derive(Deserialize)]
+    pub struct Action {
+        pub date: chrono::NaiveDate,
+        pub change: crate::Cents,
+        pub investor: crate::InvestorIndex,
+    }
+    #[derive(Deserialize)]
+    pub struct FundValue {
+        pub date: chrono::NaiveDate,
+        pub fund_value: crate::Cents,
+        pub unit_value: crate::Cents,
+    }
+    #[derive(Deserialize)]
+    pub struct Series {
+        pub fund: String,
+        pub balance: Vec<Balance>,
+        pub action: Vec<Action>,
+        pub fund_value: Vec<FundValue>,
+    }
+    #[derive(Deserialize)]
+    pub struct Table {
+        pub table: Vec<Series>,
+        pub investor: Vec<String>,
+    }
+}
+
+impl From<v0::Table> for Table {
+    fn from(t: v0::Table) -> Self {
+        Table {
+            table: t
+                .table
+                .into_iter()
+                .map(|s| Series {
+                    fund: s.fund,
+                    balance: s.balance.into_iter().map(|b| Balance { date: b.date, balance: b.balance, provenance: None }).collect(),
+                    action: s.action.into_iter().map(|a| Action { date: a.date, change: a.change, investor: a.investor, kind: ActionKind::Flow, source: None, provenance: None }).collect(),
+                    fund_value: s.fund_value.into_iter().map(|v| FundValue { date: v.date, fund_value: v.fund_value, unit_value: v.unit_value, provenance: None }).collect(),
+                    currency: Currency::Cop,
+                    fee: Vec::new(),
+                    closed: None,
+                })
+                .collect(),
+            investor: t.investor,
+            transfer: Vec::new(),
+            reviewed: Vec::new(),
+            import: Vec::new(),
+        }
+    }
+}
+
+/// Deserializes a database in any known format version.
+fn deserialize(bytes: &[u8]) -> Result<Table, String> {
+    let versioned = bytes.len() >= MAGIC.len() + 4 && bytes.starts_with(MAGIC);
+    if !versioned {
+        return bincode::deserialize::<v0::Table>(bytes).map(Table::from).map_err(|e| e.to_string());
+    }
+    let mut version_bytes = [0u8; 4];
+    version_bytes.copy_from_slice(&bytes[MAGIC.len()..MAGIC.len() + 4]);
+    let payload = &bytes[MAGIC.len() + 4..];
+    match u32::from_le_bytes(version_bytes) {
+        FORMAT_VERSION => bincode::deserialize::<Table>(payload).map_err(|e| e.to_string()),
+        v => Err(format!("Format version {} is not supported; please update fondos", v)),
+    }
+}
+
+/// Serializes the database in the current format version.
+fn serialize(table: &Table) -> Result<Vec<u8>, String> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut bytes, table).map_err(|e| e.to_string())?;
+    Ok(bytes)
+}
+
+/// Reads the database. Starts a new one if the file does not exist.
+pub fn load(funds_file_name: &str) -> Result<Table, String> {
+    let db_path = std::path::Path::new(funds_file_name);
+    if db_path.exists() {
+        let bytes = fs::read(db_path).map_err(|e| format!("Error reading the file {}: {}", funds_file_name, e))?;
+        deserialize(&bytes).map_err(|e| format!("Error reading the file {}: {}", funds_file_name, e))
+    } else {
+        println!("Starting a new file. Ctrl + C if this is a mistake.");
+        Ok(Table {
+            table: Vec::<_>::with_capacity(10),
+            investor: vec!["Default investor".to_string(), "Investor 1".to_string()],
+            transfer: Vec::new(),
+            reviewed: Vec::new(),
+            import: Vec::new(),
+        })
+    }
+}
+
+/// Writes the database through a temporary file, keeping the previous file as a timestamped backup.
+pub fn save(table: &Table, funds_file_name: &str) -> Result<(), String> {
+    let db_path = std::path::Path::new(funds_file_name);
+    let new_path = db_path.with_extension("new");
+    let new_file_name = new_path.to_string_lossy();
+    let bytes = serialize(table).map_err(|e| format!("Error writing to temporary file {}: {}", new_file_name, e))?;
+    fs::write(&new_path, bytes).map_err(|e| format!("Error writing to temporary file {}: {}", new_file_name, e))?;
+    if db_path.exists() {
+        let backup_path = db_path.with_file_name(format!(
+            "{}_backup{}.dat",
+            db_path.file_stem().unwrap_or_default().to_string_lossy(),
+            chrono::Local::now().format("%Y%m%dT%H%M%S")
+        ));
+        fs::rename(db_path, &backup_path).map_err(|e| format!("Error creating backup {}: {}", backup_path.to_string_lossy(), e))?;
+    }
+    fs::rename(&new_path, db_path).map_err(|e| format!("Error writing to file {}: {}", funds_file_name, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ActionKind, Currency};
+    #[derive(serde::Serialize)]
+    struct Balance0 {
+        date: chrono::NaiveDate,
+        balance: i64,
+    }
+    #[derive(serde::Serialize)]
+    struct FundValue0 {
+        date: chrono::NaiveDate,
+        fund_value: i64,
+        unit_value: i64,
+    }
+    #[derive(serde::Serialize)]
+    struct Action0 {
+        date: chrono::NaiveDate,
+        change: i64,
+        investor: u8,
+    }
+    #[test]
+    fn storage0() {
+        // A database written before the format had a version
+        #[derive(serde::Serialize)]
+        struct Series0 {
+            fund: String,
+            balance: Vec<Balance0>,
+            action: Vec<Action0>,
+            fund_value: Vec<FundValue0>,
+        }
+        #[derive(serde::Serialize)]
+        struct Table0 {
+            table: Vec<Series0>,
+            investor: Vec<String>,
+        }
+        let date = chrono::NaiveDate::from_ymd_opt(2021, 11, 19).unwrap();
+        let bytes = bincode::serialize(&Table0 {
+            table: vec![Series0 {
+                fund: "capital".to_string(),
+                balance: vec![Balance0 { date, balance: 100 }],
+                action: vec![Action0 { date, change: -5, investor: 1 }],
+                fund_value: vec![],
+            }],
+            investor: vec!["Default investor".to_string()],
+        })
+        .unwrap();
+        let table = super::deserialize(&bytes).unwrap();
+        assert_eq!(table.table[0].fund, "capital");
+        assert_eq!(table.table[0].balance, vec![crate::Balance { date, balance: 100, provenance: None }]);
+        assert_eq!(table.table[0].action[0].investor, 1);
+        assert_eq!(table.table[0].currency, Currency::Cop);
+        let bytes = super::serialize(&table).unwrap();
+        assert!(bytes.starts_with(super::MAGIC));
+        assert_eq!(crate::calculate_hash(&super::deserialize(&bytes).unwrap()), crate::calculate_hash(&table));
+    }
+    #[test]
+    fn storage1() {
+        let mut bytes = super::MAGIC.to_vec();
+        bytes.extend_from_slice(&999u32.to_le_bytes());
+        assert_eq!(super::deserialize(&bytes).err(), Some("Format version 999 is not supported; please update fondos".to_string()));
+    }
+    #[test]
+    fn storage2() {
+        // A database in the current format keeps every field
+        let date = chrono::NaiveDate::from_ymd_opt(2021, 11, 19).unwrap();
+        let provenance = Some(crate::provenance::Provenance { import: 0, line: 4 });
+        let source = crate::ActionSource { file: "history.txt".to_string(), line: "19/11/2021\tCapital\tAporte\tx\t$.05".to_string(), occurrence: 1 };
+        let key = |fund: &str, change| crate::transfer::ActionKey { fund: fund.to_string(), date, change };
+        let table = crate::Table {
+            table: vec![crate::Series {
+                fund: "consumo global".to_string(),
+                balance: vec![crate::Balance { date, balance: 7, provenance }],
+                action: vec![crate::Action { date, change: -5, investor: 1, kind: ActionKind::FeeCharge, source: Some(source), provenance: None }],
+                fund_value: vec![crate::FundValue { date, fund_value: 9, unit_value: 3, provenance }],
+                currency: Currency::Usd,
+                fee: vec![crate::Fee { date, annual_fee: 150 }],
+                closed: Some(date),
+            }],
+            investor: vec!["Default investor".to_string(), "Investor 1".to_string()],
+            transfer: vec![crate::transfer::Transfer { out: key("consumo global", -5), into: key("capital", 5), confidence: 100, confirmed: true }],
+            reviewed: vec![key("capital", 7)],
+            import: vec![crate::provenance::Import { file: "profit.txt".to_string(), hash: 5, time: date.and_hms_opt(8, 0, 0).unwrap(), importer: "fondos".to_string() }],
+        };
+        let bytes = super::serialize(&table).unwrap();
+        assert_eq!(bytes[super::MAGIC.len()..super::MAGIC.len() + 4], 1u32.to_le_bytes());
+        let read_table = super::deserialize(&bytes).unwrap();
+        assert_eq!(crate::calculate_hash(&read_table), crate::calculate_hash(&table));
+        assert_eq!((read_table.transfer, read_table.reviewed, read_table.import), (table.transfer, table.reviewed, table.import));
+    }
+}
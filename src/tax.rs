@@ -0,0 +1,198 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Yearly figures for the Colombian income declaration (declaración de renta).
+//!
+//! Balances are not recorded per investor. The balance of a fund is split among investors in proportion to their net contributions up to each 31 December.
+//! When a fund has no balance on 31 December of the previous year, the first balance of the year, less the actions before it, stands in for it.
+
+use crate::{format_cents, Cents, Series, Table};
+use std::io::Write as IoWrite;
+
+#[derive(Clone, Debug, PartialEq)]
+/// Figures of a fund for an investor in a year, to be stored in tax_report_<year>.csv.
+pub struct TaxRecord {
+    pub fund: String,
+    pub investor: String,
+    /// Date of the last balance on or before 31 December; None if the fund has no balance yet.
+    pub balance_date: Option<chrono::NaiveDate>,
+    /// Balance on 31 December
+    pub balance: Cents,
+    /// Balance on 31 December of the previous year
+    pub previous_balance: Cents,
+    /// Sum of deposits during the year
+    pub contributions: Cents,
+    /// Sum of withdrawals during the year, as a positive amount
+    pub withdrawals: Cents,
+    /// Balance change not explained by contributions and withdrawals
+    pub taxable_income: Cents,
+}
+
+/// Last balance on or before a date, with its date.
+fn balance_on(series: &Series, date: chrono::NaiveDate) -> Option<(chrono::NaiveDate, Cents)> {
+    series.balance.iter().rev().find(|b| b.date <= date).map(|b| (b.date, b.balance))
+}
+
+/// Share of each investor in a fund on a date, in proportion to their net contributions. Investors with net withdrawals have no share.
+pub fn shares(series: &Series, n_investors: usize, date: chrono::NaiveDate) -> Vec<f64> {
+    let mut net = vec![0 as Cents; n_investors];
+    for a in series.action.iter().filter(|a| a.is_flow() && a.date <= date) {
+        if let Some(n) = net.get_mut(a.investor as usize) {
+            *n += a.change;
+        }
+    }
+    let total: Cents = net.iter().filter(|n| **n > 0).sum();
+    if total == 0 {
+        // Without contributions on record, the balance belongs to the default investor
+        let mut shares = vec![0.; n_investors];
+        shares[0] = 1.;
+        return shares;
+    }
+    net.iter().map(|n| if *n > 0 { *n as f64 / total as f64 } else { 0. }).collect()
+}
+
+/// Calculates the figures of every fund and investor in a year. Funds without balances or actions up to the end of the year are left out.
+pub fn tax_report(table: &Table, year: i32) -> Vec<TaxRecord> {
+    let year_end = chrono::NaiveDate::from_ymd_opt(year, 12, 31).unwrap();
+    let previous_year_end = chrono::NaiveDate::from_ymd_opt(year - 1, 12, 31).unwrap();
+    let n_investors = table.investor.len().max(1);
+    let mut records = Vec::new();
+    for series in table.table.iter() {
+        let balance = balance_on(series, year_end);
+        let year_actions: Vec<_> = series.action.iter().filter(|a| a.is_flow() && a.date > previous_year_end && a.date <= year_end).collect();
+        let previous_balance = match balance_on(series, previous_year_end) {
+            Some(b) => b.1,
+            None => series
+                .balance
+                .iter()
+                .find(|b| b.date > previous_year_end && b.date <= year_end)
+                .map_or(0, |first| first.balance - year_actions.iter().filter(|a| a.date < first.date).map(|a| a.change).sum::<Cents>()),
+        };
+        if balance.is_none() && year_actions.is_empty() {
+            continue;
+        }
+        let current_shares = shares(series, n_investors, year_end);
+        let previous_shares = shares(series, n_investors, previous_year_end);
+        for investor in 0..n_investors {
+            let contributions: Cents = year_actions.iter().filter(|a| a.investor as usize == investor && a.change > 0).map(|a| a.change).sum();
+            let withdrawals: Cents = -year_actions.iter().filter(|a| a.investor as usize == investor && a.change < 0).map(|a| a.change).sum::<Cents>();
+            let investor_balance = (balance.map_or(0, |b| b.1) as f64 * current_shares[investor]).round() as Cents;
+            let investor_previous_balance = (previous_balance as f64 * previous_shares[investor]).round() as Cents;
+            if investor_balance == 0 && investor_previous_balance == 0 && contributions == 0 && withdrawals == 0 {
+                continue;
+            }
+            records.push(TaxRecord {
+                fund: series.fund.clone(),
+                investor: table.investor.get(investor).cloned().unwrap_or_else(|| format!("Investor {}", investor)),
+                balance_date: balance.map(|b| b.0),
+                balance: investor_balance,
+                previous_balance: investor_previous_balance,
+                contributions,
+                withdrawals,
+                taxable_income: investor_balance - investor_previous_balance - contributions + withdrawals,
+            });
+        }
+    }
+    records
+}
+
+/// Writes the figures to a CSV file.
+pub fn write_csv(records: &[TaxRecord], csv_file_name: &str) -> Result<(), String> {
+    let csv_file = crate::create_file(csv_file_name)?;
+    let csv_err = |e| format!("Error writing to {}: {}", csv_file_name, e);
+    writeln!(&csv_file, "Fund,Investor,Balance date,Balance 31 Dec,Balance previous 31 Dec,Contributions,Withdrawals,Estimated taxable income").map_err(csv_err)?;
+    for r in records {
+        let balance_date = r.balance_date.map(|d| d.to_string()).unwrap_or_default();
+        writeln!(&csv_file, "{},{},{},{},{},{},{},{}", r.fund, r.investor, balance_date, r.balance as f64 / 100.0, r.previous_balance as f64 / 100.0, r.contributions as f64 / 100.0, r.withdrawals as f64 / 100.0, r.taxable_income as f64 / 100.0).map_err(csv_err)?;
+    }
+    Ok(())
+}
+
+/// Printable summary of the figures, with totals per investor.
+pub fn summary(records: &[TaxRecord], year: i32) -> String {
+    let mut text = format!("Reporte para la declaración de renta {}\n\n", year);
+    let header = format!("{:<24} {:<20} {:>20} {:>20} {:>20} {:>20}\n", "Fondo", "Inversionista", "Saldo 31 dic", "Aportes", "Retiros", "Rendimiento");
+    text.push_str(&header);
+    text.push_str(&format!("{}\n", "-".repeat(header.trim_end().chars().count())));
+    for r in records {
+        text.push_str(&format!("{:<24} {:<20} {:>20} {:>20} {:>20} {:>20}\n", r.fund, r.investor, format_cents(r.balance), format_cents(r.contributions), format_cents(r.withdrawals), format_cents(r.taxable_income)));
+    }
+    let mut investors: Vec<&String> = records.iter().map(|r| &r.investor).collect();
+    investors.sort_unstable();
+    investors.dedup();
+    text.push('\n');
+    for investor in investors {
+        let sum = |f: fn(&TaxRecord) -> Cents| -> Cents { records.iter().filter(|r| r.investor == *investor).map(f).sum() };
+        text.push_str(&format!("{:<24} {:<20} {:>20} {:>20} {:>20} {:>20}\n", "Total", investor, format_cents(sum(|r| r.balance)), format_cents(sum(|r| r.contributions)), format_cents(sum(|r| r.withdrawals)), format_cents(sum(|r| r.taxable_income))));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Action, ActionKind, Balance, Currency, Series, Table};
+    fn date(year: i32, month: u32, day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+    fn table() -> Table {
+        Table {
+            table: vec![Series {
+                fund: "capital".to_string(),
+                balance: vec![Balance { date: date(2020, 12, 31), balance: 100000, provenance: None }, Balance { date: date(2021, 12, 30), balance: 180000, provenance: None }, Balance { date: date(2022, 1, 5), balance: 1, provenance: None }],
+                action: vec![
+                    Action { date: date(2020, 6, 1), change: 100000, investor: 0, kind: ActionKind::Flow, source: None, provenance: None },
+                    Action { date: date(2021, 3, 1), change: 100000, investor: 1, kind: ActionKind::Flow, source: None, provenance: None },
+                    Action { date: date(2021, 4, 1), change: -30000, investor: 0, kind: ActionKind::Flow, source: None, provenance: None },
+                ],
+                fund_value: vec![],
+                currency: Currency::Cop,
+                fee: vec![],
+                closed: None,
+            }],
+            investor: vec!["Default investor".to_string(), "Investor 1".to_string()],
+            transfer: vec![],
+            reviewed: vec![],
+            import: vec![],
+        }
+    }
+    #[test]
+    fn tax0() {
+        let records = super::tax_report(&table(), 2021);
+        assert_eq!(records.len(), 2);
+        let r0 = &records[0];
+        assert_eq!((r0.balance_date, r0.previous_balance, r0.contributions, r0.withdrawals), (Some(date(2021, 12, 30)), 100000, 0, 30000));
+        let r1 = &records[1];
+        assert_eq!((r1.previous_balance, r1.contributions, r1.withdrawals), (0, 100000, 0));
+        // Shares on 31 December: 70000 and 100000 of net contributions
+        assert_eq!(r0.balance + r1.balance, 180000);
+        assert_eq!(r0.balance, 74118);
+        assert_eq!(r0.taxable_income + r1.taxable_income, 10000);
+    }
+    #[test]
+    fn tax1() {
+        assert!(super::tax_report(&table(), 2019).is_empty());
+        // The first balance of 2020, less the deposit before it, stands in for the balance of 31 December 2019
+        let records = super::tax_report(&table(), 2020);
+        assert_eq!((records[0].previous_balance, records[0].contributions, records[0].taxable_income), (0, 100000, 0));
+        let mut without_deposit = table();
+        without_deposit.table[0].action.remove(0);
+        let records = super::tax_report(&without_deposit, 2020);
+        assert_eq!((records[0].previous_balance, records[0].contributions, records[0].taxable_income), (100000, 0, 0));
+        let summary = super::summary(&super::tax_report(&table(), 2021), 2021);
+        assert!(summary.contains("Total                    Investor 1"));
+    }
+}
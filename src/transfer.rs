@@ -0,0 +1,276 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Pairing of transfers between funds: every withdrawal from a fund should have a corresponding deposit into another.
+//!
+//! The bank may post the deposit some days after the withdrawal, and the amounts may differ slightly.
+//! Pairs are stored in Table::transfer; pairs with a low confidence are left for the user to confirm with fondos pair.
+
+use crate::{format_cents, parse_date_opt, parse_name, Cents, Table};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Maximum number of days between the withdrawal and the deposit of a transfer.
+pub const MAX_LAG_DAYS: i64 = 5;
+
+/// Maximum difference between the amounts of the withdrawal and the deposit, relative to the withdrawal.
+pub const MAX_AMOUNT_DIFFERENCE: f64 = 0.005;
+
+/// Minimum confidence, in percentage, to store a pair without asking the user.
+pub const AUTO_CONFIDENCE: u8 = 90;
+
+pub const PAIR_USAGE: &str = "fondos pair <fund> <d/m/y> <-$withdrawal> <fund> <d/m/y> <$deposit>";
+
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
+/// Identifies an action by fund, date, and amount.
+pub struct ActionKey {
+    pub fund: String,
+    pub date: chrono::NaiveDate,
+    pub change: Cents,
+}
+
+impl std::fmt::Display for ActionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.fund, self.date.format("%d/%m/%Y"), format_cents(self.change))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+/// Pair of a withdrawal from a fund and a deposit into another.
+pub struct Transfer {
+    pub out: ActionKey,
+    pub into: ActionKey,
+    /// Confidence of the pair in percentage, from 0 to 100.
+    pub confidence: u8,
+    /// Whether the user confirmed the pair with fondos pair.
+    pub confirmed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// Withdrawal or deposit that has no pair, with its best candidate and the confidence of that candidate.
+pub struct Unmatched {
+    pub action: ActionKey,
+    pub candidate: Option<(ActionKey, u8)>,
+}
+
+impl std::fmt::Display for Unmatched {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: no match", self.action)?;
+        match &self.candidate {
+            Some((candidate, confidence)) => {
+                let (out, into) = if self.action.change < 0 { (&self.action, candidate) } else { (candidate, &self.action) };
+                write!(f, "; best candidate {} with confidence {}%; confirm with: fondos pair \"{}\" {} {} \"{}\" {} {}", candidate, confidence, out.fund, out.date.format("%d/%m/%Y"), format_cents(out.change), into.fund, into.date.format("%d/%m/%Y"), format_cents(into.change))
+            }
+            None => write!(f, "; no candidate"),
+        }
+    }
+}
+
+/// Confidence, in percentage, that a withdrawal and a deposit are the same transfer. None if they cannot be.
+pub fn confidence(out: &ActionKey, into: &ActionKey) -> Option<u8> {
+    if out.fund == into.fund || out.change >= 0 || into.change <= 0 {
+        return None;
+    }
+    let lag = (into.date - out.date).num_days().abs();
+    let difference = (into.change + out.change).abs() as f64 / out.change.abs() as f64;
+    if lag > MAX_LAG_DAYS || difference > MAX_AMOUNT_DIFFERENCE {
+        return None;
+    }
+    Some((100. - 10. * lag as f64 - 50. * difference / MAX_AMOUNT_DIFFERENCE).round().max(0.) as u8)
+}
+
+/// Flows of the table that are not in a stored pair.
+fn unpaired(table: &Table) -> Vec<ActionKey> {
+    let mut paired: HashMap<&ActionKey, usize> = HashMap::new();
+    for t in table.transfer.iter() {
+        *paired.entry(&t.out).or_default() += 1;
+        *paired.entry(&t.into).or_default() += 1;
+    }
+    let mut keys = Vec::new();
+    for series in table.table.iter() {
+        for action in series.action.iter().filter(|a| a.is_flow()) {
+            let key = ActionKey { fund: series.fund.clone(), date: action.date, change: action.change };
+            match paired.get_mut(&key) {
+                Some(n) if *n > 0 => *n -= 1,
+                _ => keys.push(key),
+            }
+        }
+    }
+    keys
+}
+
+/// Pairs the flows that are not in a stored pair, storing the pairs with a confidence of at least AUTO_CONFIDENCE.
+/// Returns the flows that stay unmatched, sorted by date, each with its best candidate among them.
+pub fn match_transfers(table: &mut Table) -> Vec<Unmatched> {
+    let keys = unpaired(table);
+    let mut candidates = Vec::new();
+    for (i, out) in keys.iter().enumerate().filter(|(_, k)| k.change < 0) {
+        for (j, into) in keys.iter().enumerate().filter(|(_, k)| k.change > 0) {
+            if let Some(c) = confidence(out, into) {
+                candidates.push((c, i, j));
+            }
+        }
+    }
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+    let mut used = vec![false; keys.len()];
+    for (c, i, j) in candidates.iter().filter(|(c, _, _)| *c >= AUTO_CONFIDENCE) {
+        if !used[*i] && !used[*j] {
+            used[*i] = true;
+            used[*j] = true;
+            table.transfer.push(Transfer { out: keys[*i].clone(), into: keys[*j].clone(), confidence: *c, confirmed: false });
+        }
+    }
+    let mut unmatched: Vec<_> = (0..keys.len())
+        .filter(|k| !used[*k])
+        .map(|k| Unmatched {
+            action: keys[k].clone(),
+            candidate: candidates
+                .iter()
+                .filter(|(_, i, j)| (*i == k && !used[*j]) || (*j == k && !used[*i]))
+                .map(|(c, i, j)| (keys[if *i == k { *j } else { *i }].clone(), *c))
+                .next(),
+        })
+        .collect();
+    unmatched.sort_by(|a, b| a.action.date.cmp(&b.action.date).then(a.action.fund.cmp(&b.action.fund)));
+    unmatched
+}
+
+/// Pairs the flows like match_transfers, and returns the unmatched flows that were not reviewed before, or all of them if all is true.
+/// Marks the returned flows as reviewed, so that the next check does not report them again.
+pub fn check(table: &mut Table, all: bool) -> Vec<Unmatched> {
+    let unmatched: Vec<_> = match_transfers(table).into_iter().filter(|u| all || !table.reviewed.contains(&u.action)).collect();
+    for u in unmatched.iter() {
+        if !table.reviewed.contains(&u.action) {
+            table.reviewed.push(u.action.clone());
+        }
+    }
+    unmatched
+}
+
+/// Removes a stored pair and the review of an action that changed or was deleted, so that the next check pairs its other side again.
+pub fn forget(table: &mut Table, key: &ActionKey) {
+    if let Some(i) = table.transfer.iter().position(|t| t.out == *key || t.into == *key) {
+        table.transfer.remove(i);
+    }
+    if let Some(i) = table.reviewed.iter().position(|r| r == key) {
+        table.reviewed.remove(i);
+    }
+}
+
+/// Parses the arguments of command pair.
+pub fn parse_pair<I>(args_it: &mut I) -> Result<(ActionKey, ActionKey), String>
+where
+I: Iterator<Item = String>
+{
+    let mut key = |role: &str| -> Result<ActionKey, String> {
+        let fund = parse_name(args_it.next().as_deref(), || format!("Parsing fund of the {} for pair: ", role))?.to_lowercase();
+        let date = parse_date_opt(args_it.next().as_deref(), || format!("Parsing date of the {} for pair: ", role))?;
+        let change = crate::edit::parse_signed_cents(args_it.next().as_deref(), || format!("Parsing amount of the {} for pair: ", role))?;
+        Ok(ActionKey { fund, date, change })
+    };
+    let out = key("withdrawal")?;
+    let into = key("deposit")?;
+    if out.change >= 0 || into.change <= 0 {
+        return Err(format!("The withdrawal must be negative and the deposit positive. Usage:\n    {}", PAIR_USAGE));
+    }
+    Ok((out, into))
+}
+
+/// Stores a pair confirmed by the user. Returns a description for the audit trail.
+pub fn confirm(table: &mut Table, out: ActionKey, into: ActionKey) -> Result<String, String> {
+    let keys = unpaired(table);
+    for key in [&out, &into] {
+        if !keys.contains(key) {
+            return Err(format!("{} is not an action without a pair", key));
+        }
+    }
+    let description = format!("pair {} with {}", out, into);
+    let confidence = confidence(&out, &into).unwrap_or(0);
+    table.transfer.push(Transfer { out, into, confidence, confirmed: true });
+    Ok(description)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ActionKey;
+    use crate::{Action, ActionKind, Currency, Series, Table};
+    fn date(day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2021, 11, day).unwrap()
+    }
+    fn key(fund: &str, day: u32, change: i64) -> ActionKey {
+        ActionKey { fund: fund.to_string(), date: date(day), change }
+    }
+    fn series(fund: &str, actions: &[(u32, i64)]) -> Series {
+        Series {
+            fund: fund.to_string(),
+            balance: vec![],
+            action: actions.iter().map(|(day, change)| Action { date: date(*day), change: *change, investor: 0, kind: ActionKind::Flow, source: None, provenance: None }).collect(),
+            fund_value: vec![],
+            currency: Currency::Cop,
+            fee: vec![],
+            closed: None,
+        }
+    }
+    #[test]
+    fn transfer0() {
+        assert_eq!(super::confidence(&key("a", 19, -100000), &key("b", 19, 100000)), Some(100));
+        assert_eq!(super::confidence(&key("a", 19, -100000), &key("b", 21, 100000)), Some(80));
+        assert_eq!(super::confidence(&key("a", 19, -100000), &key("b", 19, 99750)), Some(75));
+        assert_eq!(super::confidence(&key("a", 19, -100000), &key("b", 25, 100000)), None);
+        assert_eq!(super::confidence(&key("a", 19, -100000), &key("b", 19, 99000)), None);
+        assert_eq!(super::confidence(&key("a", 19, -100000), &key("a", 19, 100000)), None);
+    }
+    #[test]
+    fn transfer1() {
+        let mut table = Table {
+            table: vec![series("consumo global", &[(19, -321686700), (19, -1209632)]), series("capital", &[(19, 321686700), (22, 1209000), (23, 5000)])],
+            investor: vec!["Default investor".to_string()],
+            transfer: vec![],
+            reviewed: vec![],
+            import: vec![],
+        };
+        let unmatched = super::match_transfers(&mut table);
+        assert_eq!(table.transfer.len(), 1);
+        assert_eq!((table.transfer[0].out.change, table.transfer[0].confidence), (-321686700, 100));
+        assert_eq!(unmatched.len(), 3);
+        assert_eq!(unmatched[0].action, key("consumo global", 19, -1209632));
+        assert_eq!(unmatched[0].candidate, Some((key("capital", 22, 1209000), 65)));
+        assert_eq!(unmatched[2].candidate, None);
+        assert!(unmatched[0].to_string().ends_with("fondos pair \"consumo global\" 19/11/2021 -$12,096.32 \"capital\" 22/11/2021 $12,090.00"));
+        let (out, into) = super::parse_pair(&mut ["Consumo Global", "19/11/2021", "-$12,096.32", "capital", "22/11/2021", "$12,090.00"].iter().map(|s| s.to_string())).unwrap();
+        assert!(super::confirm(&mut table, out.clone(), into.clone()).is_ok());
+        assert!(super::confirm(&mut table, out, into).is_err());
+        assert_eq!(super::match_transfers(&mut table).len(), 1);
+    }
+    #[test]
+    fn transfer2() {
+        let mut table = Table {
+            table: vec![series("capital", &[(19, 500000), (22, -100000)]), series("estable", &[(22, 99700)])],
+            investor: vec!["Default investor".to_string()],
+            transfer: vec![],
+            reviewed: vec![],
+            import: vec![],
+        };
+        let unmatched = super::check(&mut table, false);
+        assert_eq!(unmatched.iter().map(|u| u.action.change).collect::<Vec<_>>(), vec![500000, -100000, 99700]);
+        assert!(super::check(&mut table, false).is_empty());
+        table.table[1].action.push(crate::Action { date: date(23), change: 700, investor: 0, kind: ActionKind::Flow, source: None, provenance: None });
+        assert_eq!(super::check(&mut table, false).len(), 1);
+        assert_eq!(super::check(&mut table, true).len(), 4);
+        assert_eq!(table.reviewed.len(), 4);
+    }
+}
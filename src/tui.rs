@@ -0,0 +1,398 @@
+// fondos: Visualizes the evolution of investment funds at Banco Davivienda, Colombia, South America
+
+// Copyright (C) 2020 Fabio A. Correa Duran facorread@gmail.com
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Terminal user interface to explore the records of each fund. It only reads the database.
+//!
+//! It draws with plain terminal escape codes, so it works over SSH.
+
+use crate::{format_cents, parse_date, tax, ActionKind, Cents, Series, Table};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::widgets::{Block, Borders, List, ListState, Paragraph, Row, Sparkline, Table as TableWidget, TableState, Tabs};
+
+const HELP: &str = "↑↓ fondo  ←→ tabla  PgUp/PgDn desplazar  r rango  d fechas  i inversionista  q salir";
+
+/// Ranges of dates to cycle through with key r, in days before today. None means the whole history.
+const RANGES: &[Option<i64>] = &[None, Some(30), Some(90), Some(365)];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Records shown in the table.
+enum Tab {
+    Balances,
+    Actions,
+    UnitValues,
+}
+
+const TABS: &[Tab] = &[Tab::Balances, Tab::Actions, Tab::UnitValues];
+
+impl Tab {
+    fn title(&self) -> &'static str {
+        match self {
+            Tab::Balances => "Saldos",
+            Tab::Actions => "Movimientos",
+            Tab::UnitValues => "Valor de la unidad",
+        }
+    }
+}
+
+/// State of the interface.
+pub struct App {
+    table: Table,
+    today: chrono::NaiveDate,
+    fund_state: ListState,
+    tab: usize,
+    /// Index into RANGES, or None when the range was typed with key d.
+    range: Option<usize>,
+    start_date: Option<chrono::NaiveDate>,
+    end_date: Option<chrono::NaiveDate>,
+    /// Index of the investor; None shows every investor.
+    investor: Option<usize>,
+    row_state: TableState,
+    /// Text typed after key d, while typing.
+    input: Option<String>,
+    message: String,
+}
+
+impl App {
+    pub fn new(table: Table, today: chrono::NaiveDate) -> App {
+        let mut fund_state = ListState::default();
+        if !table.table.is_empty() {
+            fund_state.select(Some(0));
+        }
+        App {
+            table,
+            today,
+            fund_state,
+            tab: 0,
+            range: Some(0),
+            start_date: None,
+            end_date: None,
+            investor: None,
+            row_state: TableState::default(),
+            input: None,
+            message: HELP.to_string(),
+        }
+    }
+
+    fn series(&self) -> Option<&Series> {
+        self.fund_state.selected().and_then(|i| self.table.table.get(i))
+    }
+
+    fn in_range(&self, date: chrono::NaiveDate) -> bool {
+        self.start_date.is_none_or(|d| date >= d) && self.end_date.is_none_or(|d| date <= d)
+    }
+
+    fn range_title(&self) -> String {
+        match (self.start_date, self.end_date) {
+            (None, None) => "todo el historial".to_string(),
+            (Some(start), None) => format!("desde {}", start),
+            (None, Some(end)) => format!("hasta {}", end),
+            (Some(start), Some(end)) => format!("{} a {}", start, end),
+        }
+    }
+
+    fn investor_title(&self) -> String {
+        match self.investor {
+            None => "todos los inversionistas".to_string(),
+            Some(i) => self.table.investor.get(i).cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Balances in range; with an investor filter, the share of the investor in each balance.
+    fn balances(&self) -> Vec<(chrono::NaiveDate, Cents)> {
+        let series = match self.series() {
+            Some(s) => s,
+            None => return Vec::new(),
+        };
+        let n_investors = self.table.investor.len().max(1);
+        series
+            .balance
+            .iter()
+            .filter(|b| self.in_range(b.date))
+            .map(|b| match self.investor {
+                None => (b.date, b.balance),
+                Some(i) => (b.date, (b.balance as f64 * tax::shares(series, n_investors, b.date)[i]).round() as Cents),
+            })
+            .collect()
+    }
+
+    /// Rows of the table of the current tab, most recent first.
+    fn rows(&self) -> Vec<Vec<String>> {
+        let series = match self.series() {
+            Some(s) => s,
+            None => return Vec::new(),
+        };
+        match TABS[self.tab] {
+            Tab::Balances => self.balances().iter().rev().map(|(date, balance)| vec![date.to_string(), format_cents(*balance)]).collect(),
+            Tab::Actions => series
+                .action
+                .iter()
+                .rev()
+                .filter(|a| self.in_range(a.date) && self.investor.is_none_or(|i| a.investor as usize == i))
+                .map(|a| {
+                    let kind = match a.kind {
+                        ActionKind::Flow => "Movimiento",
+                        ActionKind::FeeCharge => "Comisión",
+                    };
+                    vec![a.date.to_string(), format_cents(a.change), kind.to_string(), self.table.investor.get(a.investor as usize).cloned().unwrap_or_default()]
+                })
+                .collect(),
+            Tab::UnitValues => series.fund_value.iter().rev().filter(|v| self.in_range(v.date)).map(|v| vec![v.date.to_string(), format_cents(v.unit_value), format_cents(v.fund_value)]).collect(),
+        }
+    }
+
+    /// Values for the sparkline, oldest first: unit values in the tab of unit values, and balances otherwise.
+    fn sparkline(&self) -> Vec<Cents> {
+        match (TABS[self.tab], self.series()) {
+            (Tab::UnitValues, Some(series)) => series.fund_value.iter().filter(|v| self.in_range(v.date)).map(|v| v.unit_value).collect(),
+            _ => self.balances().iter().map(|b| b.1).collect(),
+        }
+    }
+
+    fn select_fund(&mut self, step: i64) {
+        let n = self.table.table.len() as i64;
+        if n == 0 {
+            return;
+        }
+        let current = self.fund_state.selected().unwrap_or(0) as i64;
+        self.fund_state.select(Some((current + step).rem_euclid(n) as usize));
+        self.row_state.select(None);
+    }
+
+    fn scroll(&mut self, step: i64) {
+        let n = self.rows().len() as i64;
+        if n == 0 {
+            return;
+        }
+        let current = self.row_state.selected().map_or(-1, |r| r as i64);
+        self.row_state.select(Some((current + step).clamp(0, n - 1) as usize));
+    }
+
+    fn set_range(&mut self, range: usize) {
+        self.range = Some(range);
+        self.start_date = RANGES[range].map(|days| self.today - chrono::Duration::days(days));
+        self.end_date = None;
+        self.row_state.select(None);
+    }
+
+    /// Applies a range typed as "d/m/y d/m/y"; either date may be "-" to leave that end open.
+    fn apply_input(&mut self, input: &str) -> Result<(), String> {
+        let mut dates = input.split_whitespace();
+        let mut parse = |name: &str| -> Result<Option<chrono::NaiveDate>, String> {
+            match dates.next() {
+                None | Some("-") => Ok(None),
+                Some(d) => parse_date(d, || format!("Parsing {} date: ", name)).map(Some),
+            }
+        };
+        let start_date = parse("first")?;
+        let end_date = parse("last")?;
+        if let (Some(start), Some(end)) = (start_date, end_date) {
+            if end < start {
+                return Err("The last date precedes the first date".to_string());
+            }
+        }
+        self.range = None;
+        self.start_date = start_date;
+        self.end_date = end_date;
+        self.row_state.select(None);
+        Ok(())
+    }
+
+    /// Handles a key. Returns true to quit.
+    pub fn handle_key(&mut self, key: KeyCode) -> bool {
+        if let Some(input) = self.input.as_mut() {
+            match key {
+                KeyCode::Char(c) => input.push(c),
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Esc => {
+                    self.input = None;
+                    self.message = HELP.to_string();
+                }
+                KeyCode::Enter => {
+                    let input = self.input.take().unwrap_or_default();
+                    self.message = match self.apply_input(&input) {
+                        Ok(()) => HELP.to_string(),
+                        Err(e) => e,
+                    };
+                }
+                _ => {}
+            }
+            return false;
+        }
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Up | KeyCode::Char('k') => self.select_fund(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.select_fund(1),
+            KeyCode::Left | KeyCode::Char('h') => {
+                self.tab = (self.tab + TABS.len() - 1) % TABS.len();
+                self.row_state.select(None);
+            }
+            KeyCode::Right | KeyCode::Char('l') | KeyCode::Tab => {
+                self.tab = (self.tab + 1) % TABS.len();
+                self.row_state.select(None);
+            }
+            KeyCode::PageDown => self.scroll(10),
+            KeyCode::PageUp => self.scroll(-10),
+            KeyCode::Char('n') => self.scroll(1),
+            KeyCode::Char('p') => self.scroll(-1),
+            KeyCode::Char('r') => self.set_range(self.range.map_or(0, |r| (r + 1) % RANGES.len())),
+            KeyCode::Char('d') => {
+                self.input = Some(String::new());
+                self.message = "Fechas d/m/y d/m/y (- para dejar abierto), Enter para aplicar, Esc para cancelar".to_string();
+            }
+            KeyCode::Char('i') => {
+                let n = self.table.investor.len();
+                self.investor = match self.investor {
+                    None if n > 0 => Some(0),
+                    Some(i) if i + 1 < n => Some(i + 1),
+                    _ => None,
+                };
+                self.row_state.select(None);
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Draws the interface.
+    pub fn draw(&mut self, frame: &mut ratatui::Frame) {
+        let highlight = Style::default().fg(Color::Black).bg(Color::Yellow).add_modifier(Modifier::BOLD);
+        let [main_area, status_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [list_area, detail_area] = Layout::horizontal([Constraint::Length(28), Constraint::Min(0)]).areas(main_area);
+        let [tabs_area, table_area, sparkline_area] = Layout::vertical([Constraint::Length(3), Constraint::Min(0), Constraint::Length(8)]).areas(detail_area);
+        let funds = List::new(self.table.table.iter().map(|s| s.fund.as_str())).block(Block::default().borders(Borders::ALL).title("Fondos")).highlight_style(highlight);
+        frame.render_stateful_widget(funds, list_area, &mut self.fund_state);
+        let tabs = Tabs::new(TABS.iter().map(|t| t.title()))
+            .select(self.tab)
+            .highlight_style(highlight)
+            .block(Block::default().borders(Borders::ALL).title(format!("{}, {}", self.range_title(), self.investor_title())));
+        frame.render_widget(tabs, tabs_area);
+        let (header, widths): (&[&str], Vec<Constraint>) = match TABS[self.tab] {
+            Tab::Balances => (&["Fecha", "Saldo"], vec![Constraint::Length(12), Constraint::Length(22)]),
+            Tab::Actions => (&["Fecha", "Valor", "Tipo", "Inversionista"], vec![Constraint::Length(12), Constraint::Length(22), Constraint::Length(12), Constraint::Min(10)]),
+            Tab::UnitValues => (&["Fecha", "Unidad", "Valor del fondo"], vec![Constraint::Length(12), Constraint::Length(16), Constraint::Length(26)]),
+        };
+        let rows = self.rows();
+        let title = format!("{} registros", rows.len());
+        let table = TableWidget::new(rows.into_iter().map(Row::new), widths)
+            .header(Row::new(header.iter().copied()).style(Style::default().add_modifier(Modifier::BOLD)))
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .row_highlight_style(highlight);
+        frame.render_stateful_widget(table, table_area, &mut self.row_state);
+        self.draw_sparkline(frame, sparkline_area);
+        let status = match &self.input {
+            Some(input) => format!("{} > {}", self.message, input),
+            None => self.message.clone(),
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+    }
+
+    fn draw_sparkline(&self, frame: &mut ratatui::Frame, area: Rect) {
+        let values = self.sparkline();
+        let min = values.iter().copied().min().unwrap_or(0);
+        let max = values.iter().copied().max().unwrap_or(0);
+        let title = format!("{} a {}", format_cents(min), format_cents(max));
+        // One bar per column; bars are relative to the minimum so that small variations show
+        let width = area.width.saturating_sub(2) as usize;
+        let data: Vec<u64> = (0..width.min(values.len()))
+            .map(|column| values[column * values.len() / width.min(values.len())])
+            .map(|v| (v - min) as u64 + 1)
+            .collect();
+        let sparkline = Sparkline::default().data(data).style(Style::default().fg(Color::Yellow)).block(Block::default().borders(Borders::ALL).title(title));
+        frame.render_widget(sparkline, area);
+    }
+}
+
+/// Runs the interface until the user quits.
+pub fn run(table: Table, today: chrono::NaiveDate) -> Result<(), String> {
+    let mut app = App::new(table, today);
+    let mut terminal = ratatui::try_init().map_err(|e| format!("Error starting the terminal interface: {}", e))?;
+    let result = loop {
+        if let Err(e) = terminal.draw(|frame| app.draw(frame)) {
+            break Err(format!("Error drawing the terminal interface: {}", e));
+        }
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                if app.handle_key(key.code) {
+                    break Ok(());
+                }
+            }
+            Ok(_) => {}
+            Err(e) => break Err(format!("Error reading the keyboard: {}", e)),
+        }
+    };
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Action, ActionKind, Balance, Currency, FundValue, Series, Table};
+    use ratatui::crossterm::event::KeyCode;
+    fn date(day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2021, 11, day).unwrap()
+    }
+    fn app() -> super::App {
+        let series = |fund: &str| Series {
+            fund: fund.to_string(),
+            balance: vec![Balance { date: date(1), balance: 1000, provenance: None }, Balance { date: date(20), balance: 2000, provenance: None }],
+            action: vec![Action { date: date(1), change: 600, investor: 0, kind: ActionKind::Flow, source: None, provenance: None }, Action { date: date(1), change: 400, investor: 1, kind: ActionKind::Flow, source: None, provenance: None }],
+            fund_value: vec![FundValue { date: date(1), fund_value: 0, unit_value: 100, provenance: None }],
+            currency: Currency::Cop,
+            fee: vec![],
+            closed: None,
+        };
+        let table = Table { table: vec![series("capital"), series("estable")], investor: vec!["Default investor".to_string(), "Investor 1".to_string()], transfer: vec![], reviewed: vec![], import: vec![] };
+        super::App::new(table, date(25))
+    }
+    #[test]
+    fn tui0() {
+        let mut app = app();
+        assert_eq!(app.rows(), vec![vec!["2021-11-20".to_string(), "$20.00".to_string()], vec!["2021-11-01".to_string(), "$10.00".to_string()]]);
+        app.handle_key(KeyCode::Up);
+        assert_eq!(app.series().unwrap().fund, "estable");
+        // Last 30 days, then a typed range
+        app.handle_key(KeyCode::Char('r'));
+        assert_eq!(app.start_date, Some(date(25) - chrono::Duration::days(30)));
+        for key in "d10/11/2021 -".chars() {
+            app.handle_key(KeyCode::Char(key));
+        }
+        app.handle_key(KeyCode::Enter);
+        assert_eq!((app.start_date, app.end_date), (Some(date(10)), None));
+        assert_eq!(app.rows().len(), 1);
+        assert!(!app.handle_key(KeyCode::Char('x')));
+        assert!(app.handle_key(KeyCode::Char('q')));
+    }
+    #[test]
+    fn tui1() {
+        let mut app = app();
+        app.handle_key(KeyCode::Char('i'));
+        app.handle_key(KeyCode::Char('i'));
+        // Investor 1 contributed 400 of 1000
+        assert_eq!(app.balances(), vec![(date(1), 400), (date(20), 800)]);
+        app.handle_key(KeyCode::Right);
+        assert_eq!(app.rows(), vec![vec!["2021-11-01".to_string(), "$4.00".to_string(), "Movimiento".to_string(), "Investor 1".to_string()]]);
+        let mut terminal = ratatui::Terminal::new(ratatui::backend::TestBackend::new(100, 30)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let screen: String = terminal.backend().buffer().content().iter().map(|c| c.symbol()).collect();
+        assert!(screen.contains("Investor 1"));
+        assert!(screen.contains("1 registros"));
+    }
+}